@@ -7,6 +7,8 @@
 use clap::{ColorChoice, CommandFactory, Parser};
 
 use crate::commands;
+use crate::common::config::GlobalConfig;
+use crate::print_error_msg;
 use crate::subcommand_define;
 
 /// 创建 cli 结构体， 用于解析命令行参数
@@ -21,6 +23,23 @@ pub struct Cli {
     /// 创建 cmds 字段，用于存储解析的子命令
     #[command(subcommand)]
     pub cmds: Option<MainCmds>,
+
+    /// 列表类命令的输出格式
+    #[arg(
+        long,
+        global = true,
+        value_parser = ["table", "json", "yaml", "toml", "csv"],
+        help = "列表类命令的输出格式, table 供人阅读, 其余格式供脚本解析, 默认为 table"
+    )]
+    pub output: Option<String>,
+
+    /// 安装根目录, 模块安装在 <root>/modules
+    #[arg(
+        long,
+        global = true,
+        help = "安装根目录, 未指定时依次使用 WAYTOUS_ROOT 环境变量、配置文件中的 root, 默认为 /opt/waytous"
+    )]
+    pub root: Option<String>,
 }
 
 /// 枚举定义主命令下的所有子命令
@@ -43,6 +62,12 @@ pub enum MainCmds {
     #[command(about = "生成并配置 cli 的自动补全脚本")]
     Autocompletion {},
 
+    #[command(about = "查看 cli 的全局配置")]
+    Config {
+        #[command(subcommand)]
+        sub_cmd: subcommand_define::GlobalConfigCmds,
+    },
+
     #[command(about = "自动驾驶系统启动管理器")]
     Supervisord {
         #[command(subcommand)]
@@ -59,20 +84,34 @@ impl Cli {
     pub fn run(&self) {
         match &self.cmds {
             Some(MainCmds::Module { sub_cmd }) => {
-                commands::module::module(sub_cmd);
+                commands::module::module(sub_cmd, &self.load_config());
             }
             Some(MainCmds::Artifact { sub_cmd }) => {
-                commands::artifact::artifact(sub_cmd);
+                commands::artifact::artifact(sub_cmd, &self.load_config());
+            }
+            Some(MainCmds::Config { sub_cmd }) => {
+                commands::config::config(sub_cmd, &self.load_config());
             }
             Some(MainCmds::Autocompletion {}) => {
                 commands::autocompletion::GenerateAutoCompletion::process();
             }
             Some(MainCmds::Supervisord { sub_cmd }) => {
-                commands::supervisord::supervisord(sub_cmd);
+                commands::supervisord::supervisord(sub_cmd, &self.load_config());
             }
             None => {
                 println!("{}", Cli::command().render_help())
             }
         }
     }
+
+    /// 加载全局配置, 命令行参数的优先级最高, 配置文件解析失败时退出
+    fn load_config(&self) -> GlobalConfig {
+        match GlobalConfig::load(&[("root", &self.root), ("output", &self.output)]) {
+            Ok(config) => config,
+            Err(err) => {
+                print_error_msg!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 }
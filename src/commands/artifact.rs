@@ -4,13 +4,21 @@
  * File: artifact
  */
 
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
 use comfy_table::presets::{UTF8_FULL, UTF8_NO_BORDERS};
 use comfy_table::{ContentArrangement, Table};
+use flate2::read::GzDecoder;
+use tar::Archive;
 
+use crate::common::config::GlobalConfig;
+use crate::common::{manifest, package, schema, version};
 use crate::subcommand_define;
-use crate::{common, print_error_msg, print_info_msg, print_tips_msg};
+use crate::{common, print_error_msg, print_info_msg, print_tips_msg, print_warning_msg};
 
-pub fn artifact(sub_cmd: &subcommand_define::ArtifactCmds) {
+pub fn artifact(sub_cmd: &subcommand_define::ArtifactCmds, config: &GlobalConfig) {
     match sub_cmd {
         subcommand_define::ArtifactCmds::Write { sub_cmd } => match sub_cmd {
             subcommand_define::WriteSubCmd::ModuleImage {
@@ -19,15 +27,30 @@ pub fn artifact(sub_cmd: &subcommand_define::ArtifactCmds) {
                 software_version,
                 mode,
                 files,
+                device_type,
             } => {
-                write_module_image(type_value, artifact_name, software_version, mode, files);
+                // 命令行参数优先, 其次为全局配置
+                let device_type = device_type
+                    .clone()
+                    .unwrap_or_else(|| config.get("device_type").to_string());
+                write_module_image(
+                    type_value,
+                    artifact_name,
+                    software_version,
+                    mode,
+                    files,
+                    &device_type,
+                );
             }
-            _ => {}
+            subcommand_define::WriteSubCmd::Boostrap { .. } => {}
+            subcommand_define::WriteSubCmd::RootfsImage { .. } => {}
         },
         subcommand_define::ArtifactCmds::Read { file } => {
             get_artifact_info(file);
         }
-        subcommand_define::ArtifactCmds::Install { .. } => {}
+        subcommand_define::ArtifactCmds::Install { file } => {
+            install_artifact(file, &config.root());
+        }
         subcommand_define::ArtifactCmds::Modify { .. } => {}
         subcommand_define::ArtifactCmds::Cp { .. } => {}
         subcommand_define::ArtifactCmds::List { .. } => {}
@@ -48,6 +71,100 @@ fn get_artifact_info(file: &str) {
     );
 }
 
+/// 安装指定的制品文件, 安装后为制品中没有留下文件清单的模块写入制品携带的文件清单
+/// # Arguments
+/// * file - 制品文件名称
+/// * root - 安装根目录
+/// # Return
+/// Null
+fn install_artifact(file: &str, root: &Path) {
+    // 安装前从制品中读取文件清单, 校验值以制品内容为准, 不采用安装后磁盘上的文件
+    let manifests = match payload_manifests(Path::new(file)) {
+        Ok(manifests) => manifests,
+        Err(err) => {
+            print_warning_msg!("无法读取 {} 中的文件清单: {}", file, err);
+            Vec::new()
+        }
+    };
+
+    print_info_msg!("正在安装制品 {}...", file);
+    if !common::common::system("mender-update", vec!["install", file]) {
+        print_error_msg!("{} 制品安装失败", file);
+        std::process::exit(1);
+    }
+
+    // 只处理制品中的模块, deb 与 run 安装包安装时已写入文件清单
+    for module_manifest in &manifests {
+        // 名称与版本号来自制品内容, 作为路径使用前检查, 防止写到模块目录之外
+        let checked = schema::validate_module_name(&module_manifest.name)
+            .and_then(|_| version::parse_version(&module_manifest.version).map(|_| ()));
+        if let Err(err) = checked {
+            print_error_msg!("{} 中的文件清单无效, 不记录: {}", file, err);
+            continue;
+        }
+        let module_dir = common::common::get_module_version_dir(
+            root,
+            &module_manifest.name,
+            &module_manifest.version,
+        );
+        if !module_dir.is_dir() || manifest::manifest_path(&module_dir).exists() {
+            continue;
+        }
+        match manifest::write_manifest(&module_dir, module_manifest) {
+            Ok(()) => print_info_msg!("已记录 {} 的文件清单", module_manifest.name),
+            Err(err) => print_error_msg!("{}", err),
+        }
+    }
+    print_info_msg!("{} 制品安装完成", file);
+}
+
+/// 读取 mender 制品中每个安装包的文件清单
+/// 制品为 tar 格式, 其中 data/<序号>.tar.gz 为负载, 负载中是 deb 或 run 安装包
+/// # Arguments
+/// * file - 制品文件路径
+/// # Return
+/// * Ok(Vec<Manifest>) - 负载中模块的文件清单
+/// * Err(String) - 制品读取失败的原因
+fn payload_manifests(file: &Path) -> Result<Vec<manifest::Manifest>, String> {
+    let artifact =
+        fs::File::open(file).map_err(|e| format!("打开 {} 失败: {}", file.display(), e))?;
+    let mut manifests = Vec::new();
+    let mut archive = Archive::new(artifact);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry
+            .path()
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .to_string();
+        if !path.starts_with("data/") {
+            continue;
+        }
+        if !path.ends_with(".tar.gz") {
+            return Err(format!("不支持的负载压缩格式 {}", path));
+        }
+        let mut payload = Archive::new(GzDecoder::new(entry));
+        for package in payload.entries().map_err(|e| e.to_string())? {
+            let mut package = package.map_err(|e| e.to_string())?;
+            let name = package
+                .path()
+                .map_err(|e| e.to_string())?
+                .to_string_lossy()
+                .to_string();
+            if !name.ends_with(".deb") && !name.ends_with(".run") {
+                print_warning_msg!("{} 不是 deb 或 run 安装包, 不记录文件清单", name);
+                continue;
+            }
+            let mut contents = Vec::new();
+            package
+                .read_to_end(&mut contents)
+                .map_err(|e| e.to_string())?;
+            manifests.extend(package::read_package_manifests(&name, &contents)?);
+        }
+    }
+    Ok(manifests)
+}
+
 /// 根据传入的参数来制作对应的 OTA 软件制品
 /// # Arguments
 /// * type_value - 制品类型(deb zip run tar)
@@ -55,6 +172,7 @@ fn get_artifact_info(file: &str) {
 /// * device_type - 设备类型
 /// * software_version - 软件版本
 /// * files - 需要打包的文件列表
+/// * device_type - 设备类型, 为空时根据当前系统生成
 /// # Return
 /// * Null
 fn write_module_image(
@@ -63,17 +181,22 @@ fn write_module_image(
     software_version: &str,
     mode: &str,
     files: &Vec<String>,
+    device_type: &str,
 ) {
     // 组装最终的制品文件名称
     // 制品名字-版本号-当前时间-ubuntu_代号_平台-制品模式.mender
     let artifact_full_name = format!("{}-{}.mender", artifact_name, software_version);
 
-    // 获取当前设备类型信息
-    let device_type = format!(
-        "{}-{}",
-        common::common::get_system_info().architecture,
-        common::common::get_system_info().code_name
-    );
+    // 未指定设备类型时根据当前系统生成
+    let device_type = if device_type.is_empty() {
+        format!(
+            "{}-{}",
+            common::common::get_system_info().architecture,
+            common::common::get_system_info().code_name
+        )
+    } else {
+        device_type.to_string()
+    };
 
     print_tips_msg!("{}", mode);
     // 组装打包所需的参数
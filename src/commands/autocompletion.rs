@@ -64,11 +64,9 @@ impl GenerateAutoCompletion {
     fn is_line_in_file(file_path: &PathBuf, line_to_check: &str) -> bool {
         if let Ok(file) = fs::File::open(file_path) {
             let reader = BufReader::new(file);
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    if line.trim() == line_to_check.trim() {
-                        return true;
-                    }
+            for line in reader.lines().map_while(Result::ok) {
+                if line.trim() == line_to_check.trim() {
+                    return true;
                 }
             }
         }
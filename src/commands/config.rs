@@ -0,0 +1,43 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-23
+ * File: config
+ */
+
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+
+use crate::common::config::GlobalConfig;
+use crate::common::output;
+use crate::print_info_msg;
+use crate::subcommand_define;
+
+pub fn config(sub_cmd: &subcommand_define::GlobalConfigCmds, config: &GlobalConfig) {
+    match sub_cmd {
+        subcommand_define::GlobalConfigCmds::Show {} => {
+            show_config(config);
+        }
+    }
+}
+
+/// 显示合并后生效的全局配置以及每个配置项的来源
+/// # Arguments
+/// * `config` - 全局配置
+fn show_config(config: &GlobalConfig) {
+    if !output::is_table(config.output()) {
+        output::print(config.output(), "config", config.entries());
+        return;
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Key", "Value", "Source"]);
+    for entry in config.entries() {
+        table.add_row(vec![&entry.key, &entry.value, &entry.source]);
+    }
+    print_info_msg!("{}", table.to_string());
+}
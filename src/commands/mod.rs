@@ -6,5 +6,6 @@
 
 pub mod artifact;
 pub mod autocompletion;
+pub mod config;
 pub mod module;
 pub mod supervisord;
@@ -4,192 +4,787 @@
  * File: module
  */
 
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use comfy_table::modifiers::UTF8_ROUND_CORNERS;
 use comfy_table::presets::UTF8_FULL;
 use comfy_table::{ContentArrangement, Table};
+use serde::Serialize;
 use toml;
 
-
-
+use crate::commands::supervisord;
+use crate::common::config::GlobalConfig;
+use crate::common::{backup, deb, depends, graph, manifest, output, run, schema, version};
 use crate::subcommand_define;
-use crate::{common::common, print_debug_msg, print_error_msg, print_info_msg};
+use crate::{common::common, print_error_msg, print_info_msg, print_tips_msg, print_warning_msg};
 
-pub fn module(sub_cmd: &subcommand_define::ModuleCmds) {
+pub fn module(sub_cmd: &subcommand_define::ModuleCmds, config: &GlobalConfig) {
+    let root = config.root();
+    let root = root.as_path();
+    let output = config.output();
     match sub_cmd {
         subcommand_define::ModuleCmds::Create { name, no_demo } => {
             create(name, *no_demo);
         }
-        subcommand_define::ModuleCmds::Build { jobs } => {
-            build(jobs);
+        subcommand_define::ModuleCmds::Build { jobs, workspace } => {
+            if *workspace {
+                build_workspace(jobs);
+            } else {
+                build(jobs);
+            }
+        }
+        subcommand_define::ModuleCmds::Version { sub_cmd } => match sub_cmd {
+            subcommand_define::VersionSubCmd::Bump { part, pre_id, tag } => {
+                bump_current_module_version(part, pre_id, *tag);
+            }
+        },
+        subcommand_define::ModuleCmds::List { name, tag } => {
+            print_installed_module_list(root, name, tag, output);
+        }
+        subcommand_define::ModuleCmds::ListFiles { name, tree, check } => {
+            if *check {
+                check_installed_module_files(root, name, output);
+            } else {
+                print_installed_module_files(root, name, *tree, output);
+            }
+        }
+        subcommand_define::ModuleCmds::Remove { name, force } => {
+            remove(root, name, *force);
+        }
+        subcommand_define::ModuleCmds::Rollback {
+            name,
+            version,
+            force,
+        } => {
+            rollback(root, name, version, *force);
         }
-        subcommand_define::ModuleCmds::List { name } => {
-            print_installed_module_list(name);
+        subcommand_define::ModuleCmds::Activate { name, version } => {
+            activate(root, name, version);
         }
-        subcommand_define::ModuleCmds::ListFiles { name } => {
-            print_installed_module_files(name);
+        subcommand_define::ModuleCmds::Verify { name } => {
+            verify_installed_modules(root, name, output);
+        }
+        subcommand_define::ModuleCmds::Package {
+            format,
+            install_dir,
+            output_dir,
+        } => {
+            package(format, install_dir, output_dir);
         }
         subcommand_define::ModuleCmds::Config { sub_cmd } => match sub_cmd {
-            subcommand_define::ConfigSubCmd::Set {
-                name,
-                version,
-                architecture,
-                author,
-                description,
-            } => {
-                set_current_module_config(name, version, architecture, author, description);
+            subcommand_define::ConfigSubCmd::Set(args) => {
+                set_current_module_config(args);
             }
             subcommand_define::ConfigSubCmd::Get {} => {
-                print_current_module_config();
+                print_current_module_config(output);
+            }
+            subcommand_define::ConfigSubCmd::Validate { file } => {
+                validate_module_config(file, output);
             }
         },
     }
 }
 
+/// 检查命令行传入的模块名称, 名称用于拼接安装目录, 无效时退出
+fn check_module_name(name: &str) {
+    if let Err(err) = schema::validate_module_name(name) {
+        print_error_msg!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// 模块列表中的一条记录, 对应模块的一个已安装版本
+#[derive(Serialize)]
+struct ModuleListRecord {
+    /// 是否为当前生效的版本
+    active: bool,
+
+    #[serde(flatten)]
+    info: common::ModuleInfo,
+}
+
 /// 获取当前系统中已经安装的自动驾驶系统模块列表
 /// # Argment
-/// * 'Null'
+/// * `name` - 模块名称, 为空时列出所有已安装的模块
+/// * `tag` - 只列出带有该标签的模块版本, 为空时不筛选
+/// * `output` - 输出格式
 ///  # Return
 ///  * 'Null'
-fn print_installed_module_list(name: &Option<String>) {
+fn print_installed_module_list(
+    root: &Path,
+    name: &Option<String>,
+    tag: &Option<String>,
+    output: &str,
+) {
+    // 所有已安装模块的版本号, 用于检查依赖是否满足
+    let installed_versions = common::get_installed_module_versions(root);
+    let mut listed_modules: Vec<(String, common::VersionMetaData)> = Vec::new();
+    let mut records: Vec<ModuleListRecord> = Vec::new();
+    let mut failed = false;
+
+    let modules = match name {
+        Some(name) => {
+            check_module_name(name);
+            vec![name.clone()]
+        }
+        None => common::get_installed_module_list(root),
+    };
+    for module in modules {
+        match common::get_installed_module_info(root, &module) {
+            Ok(module_info) => {
+                let module_records: Vec<ModuleListRecord> =
+                    module_version_records(root, &module, output, &mut failed)
+                        .into_iter()
+                        .filter(|record| {
+                            tag.as_ref()
+                                .is_none_or(|tag| record.info.version_meta_data.tags.contains(tag))
+                        })
+                        .collect();
+                if module_records.is_empty() {
+                    continue;
+                }
+                records.extend(module_records);
+                listed_modules.push((module, module_info.version_meta_data));
+            }
+            Err(err) if name.is_some() => {
+                output::print_error(output, &format!("读取模块 {} 失败: {}", module, err));
+                std::process::exit(1);
+            }
+            Err(err) => {
+                output::print_error(output, &format!("读取模块 {} 失败: {}", module, err));
+                failed = true;
+            }
+        }
+    }
+
+    if !output::is_table(output) {
+        output::print(output, "modules", &records);
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut table = Table::new();
     table.load_preset(UTF8_FULL);
     table.apply_modifier(UTF8_ROUND_CORNERS);
     table.set_content_arrangement(ContentArrangement::Dynamic);
     table.set_header(vec![
         "ID",
+        "Active",
         "Name",
         "Version",
         "Architecture",
         "Author",
         "Description",
+        "Size",
+        "Files",
     ]);
+    for (index, record) in records.iter().enumerate() {
+        let metadata = &record.info.version_meta_data;
+        table.add_row(vec![
+            (index + 1).to_string(),
+            if record.active { "*" } else { "" }.to_string(),
+            metadata.name.clone(),
+            metadata.version.clone(),
+            metadata.platform.clone(),
+            metadata.author.clone(),
+            metadata.description.clone(),
+            common::format_size(record.info.module_file_size),
+            record.info.module_file_num.to_string(),
+        ]);
+    }
 
-    match name {
-        Some(name) => match common::get_installed_module_info(name) {
-            Ok(module_info) => {
-                table.add_row(vec![
-                    "1".to_string(),
-                    name.to_string(),
-                    module_info.version_meta_data.version,
-                    module_info.version_meta_data.platform,
-                    module_info.version_meta_data.author,
-                    module_info.version_meta_data.description,
-                ]);
+    // 整个安装目录的统计信息, 包含不属于任何模块的文件
+    let modules_path = common::get_modules_path(root);
+    if name.is_none() {
+        let (total_size, total_num) = common::get_directory_usage(&modules_path);
+        table.add_row(vec![
+            "".to_string(),
+            "".to_string(),
+            "Total".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            common::format_size(total_size),
+            total_num.to_string(),
+        ]);
+    }
+    print_info_msg!("{}", table.to_string());
+    if name.is_none() {
+        print_info_msg!("安装目录: {}", modules_path.display());
+    }
+
+    // 输出未满足的依赖以及冲突
+    for (module, metadata) in &listed_modules {
+        for problem in depends::find_unmet_dependencies(metadata, &installed_versions) {
+            print_warning_msg!("{}: {}", module, problem);
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-                print_info_msg!("{}", table.to_string());
+/// 获取模块已安装的每个版本的信息, 并标记当前生效的版本, 读取失败的版本会输出错误并设置 failed
+fn module_version_records(
+    root: &Path,
+    module: &str,
+    output: &str,
+    failed: &mut bool,
+) -> Vec<ModuleListRecord> {
+    let legacy = common::is_legacy_module_layout(root, module);
+    let active_version = common::get_module_active_version(root, module);
+    let mut records = Vec::new();
+    for module_version in common::get_installed_module_version_list(root, module) {
+        let module_info = if legacy {
+            common::get_installed_module_info(root, module)
+        } else {
+            common::get_installed_module_version_info(root, module, &module_version)
+        };
+        match module_info {
+            Ok(info) => records.push(ModuleListRecord {
+                active: legacy || active_version.as_deref() == Some(module_version.as_str()),
+                info,
+            }),
+            Err(err) => {
+                output::print_error(output, &err);
+                *failed = true;
             }
+        }
+    }
+    records
+}
+
+fn create(name: &str, no_demo: bool) {
+    print_info_msg!("创建 {}, demo: {}", name, no_demo);
+}
+
+/// 模块的构建结果
+#[derive(Clone, Copy, PartialEq)]
+enum BuildStatus {
+    /// 构建成功
+    Success,
+    /// 构建失败
+    Failed,
+    /// 依赖的模块构建失败, 跳过构建
+    Skipped,
+}
+
+impl BuildStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::Success => "成功",
+            BuildStatus::Failed => "失败",
+            BuildStatus::Skipped => "跳过",
+        }
+    }
+}
+
+/// 构建当前目录下的模块
+fn build(jobs: &u32) {
+    if build_module(Path::new("."), *jobs) {
+        print_info_msg!("模块构建完成");
+    } else {
+        print_error_msg!("模块构建失败");
+        std::process::exit(1);
+    }
+}
+
+/// 使用 cmake 构建指定目录下的模块
+/// # Arguments
+/// * `path` - 模块所在目录
+/// * `jobs` - 构建线程数量
+/// # Return
+/// * true - 构建成功
+/// * false - 构建失败
+fn build_module(path: &Path, jobs: u32) -> bool {
+    let source_dir = path.to_string_lossy().to_string();
+    let build_dir = path
+        .join(common::module_constants::BUILD_DIR_NAME)
+        .to_string_lossy()
+        .to_string();
+    let jobs = jobs.max(1).to_string();
+
+    let success = common::system("cmake", vec!["-S", &source_dir, "-B", &build_dir])
+        && common::system("cmake", vec!["--build", &build_dir, "-j", &jobs]);
+    if success {
+        if let Err(err) = stamp_build_info(path) {
+            print_warning_msg!("{}", err);
+        }
+    }
+    success
+}
+
+/// 将构建时的 git 提交与构建时间写入构建目录中的 version.toml, module package 从这里读取构建信息,
+/// 模块源码中的 version.toml 保持不变. 不在 git 仓库中时保留 version.toml 中的 git 提交,
+/// 工作区有未提交的修改时提交以 -dirty 结尾
+/// # Arguments
+/// * `path` - 模块所在目录
+/// # Return
+/// * Ok(()) - 写入成功
+/// * Err(String) - version.toml 读取或写入失败, 或无法读取 git 工作区的状态
+fn stamp_build_info(path: &Path) -> Result<(), String> {
+    let meta_path = path.join(common::module_constants::MODULE_META_DATA_NAME);
+    if !meta_path.exists() {
+        return Ok(());
+    }
+    let mut metadata = common::read_version_meta_data(&meta_path)?;
+
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    if let Some(commit) = git(&["rev-parse", "HEAD"]) {
+        // 路径相对于模块目录, 构建目录与默认的安装目录属于构建产物, 不计入未提交的修改
+        let build_dir = format!(":!{}", common::module_constants::BUILD_DIR_NAME);
+        let install_dir = format!(":!{}", common::module_constants::INSTALL_DIR_NAME);
+        let status = git(&["status", "--porcelain", "--", ".", &build_dir, &install_dir])
+            .ok_or_else(|| format!("读取 {} 的 git 状态失败, 未记录构建信息", path.display()))?;
+        metadata.git_commit = if status.is_empty() {
+            commit
+        } else {
+            format!("{}-dirty", commit)
+        };
+    }
+    metadata.build_time = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+
+    let build_dir = path.join(common::module_constants::BUILD_DIR_NAME);
+    std::fs::create_dir_all(&build_dir)
+        .map_err(|e| format!("创建 {} 失败: {}", build_dir.display(), e))?;
+    common::write_version_meta_data(
+        &build_dir.join(common::module_constants::MODULE_META_DATA_NAME),
+        &metadata,
+    )
+}
+
+/// 按依赖顺序构建工作空间中的所有模块, 相互独立的模块并行构建, 所有模块共用 jobs 个线程
+fn build_workspace(jobs: &u32) {
+    let modules = match common::find_workspace_modules(Path::new(".")) {
+        Ok(modules) => modules,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if modules.is_empty() {
+        print_error_msg!(
+            "当前目录下没有找到包含 {} 的模块",
+            common::module_constants::MODULE_META_DATA_NAME
+        );
+        return;
+    }
+
+    // 只保留工作空间内部的依赖关系, 外部依赖由已安装的模块提供
+    let mut dependency_graph = graph::DependencyGraph::new();
+    for module in &modules {
+        let module_depends = match depends::parse_dependencies(&module.version_meta_data.depends) {
+            Ok(module_depends) => module_depends,
             Err(err) => {
-                print_debug_msg!("哦豁{}", err.to_string());
+                print_error_msg!("{}: {}", module.path.display(), err);
+                std::process::exit(1);
             }
-        },
-        None => {
-            let mut count: u32 = 0;
-            for module in common::get_installed_module_list() {
-                count += 1;
-                match common::get_installed_module_info(&module) {
-                    Ok(module_info) => {
-                        table.add_row(vec![
-                            count.to_string(),
-                            module,
-                            module_info.version_meta_data.version,
-                            module_info.version_meta_data.platform,
-                            module_info.version_meta_data.author,
-                            module_info.version_meta_data.description,
-                        ]);
-                    }
-                    Err(err) => {
-                        print_error_msg!("{}", err.to_string());
-                    }
+        };
+        dependency_graph.insert(
+            module.version_meta_data.name.clone(),
+            module_depends.into_iter().map(|d| d.name).collect(),
+        );
+    }
+    let order = match graph::topological_sort(&dependency_graph) {
+        Ok(order) => order,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let max_jobs = (*jobs).max(1) as usize;
+    let mut results: BTreeMap<String, (BuildStatus, Duration)> = BTreeMap::new();
+    let mut started: BTreeSet<String> = BTreeSet::new();
+    // 正在构建的模块占用的线程数之和, 不超过 jobs
+    let mut used_jobs: usize = 0;
+    let (sender, receiver) = mpsc::channel();
+
+    print_info_msg!("共发现 {} 个模块, 构建顺序: {}", order.len(), order.join(" -> "));
+    loop {
+        let mut ready_modules: Vec<String> = Vec::new();
+        for name in &order {
+            if started.contains(name) || results.contains_key(name) {
+                continue;
+            }
+
+            let depends = &dependency_graph[name];
+            let depends_in_workspace = depends.iter().filter(|d| dependency_graph.contains_key(*d));
+            let mut ready = true;
+            let mut skipped = false;
+            for depend in depends_in_workspace {
+                match results.get(depend) {
+                    Some((BuildStatus::Success, _)) => {}
+                    Some(_) => skipped = true,
+                    None => ready = false,
                 }
             }
-            print_info_msg!("{}", table.to_string());
+
+            if skipped {
+                print_error_msg!("跳过 {}: 依赖的模块构建失败", name);
+                results.insert(name.clone(), (BuildStatus::Skipped, Duration::ZERO));
+            } else if ready {
+                ready_modules.push(name.clone());
+            }
+        }
+
+        // 空闲的线程平均分给可以开始构建的模块, 每个模块至少 1 个线程,
+        // 单个模块或链式依赖时一个模块即可使用全部线程
+        let free_jobs = max_jobs - used_jobs;
+        let count = ready_modules.len().min(free_jobs);
+        for (index, name) in ready_modules.into_iter().take(count).enumerate() {
+            let module_jobs = free_jobs / count + usize::from(index < free_jobs % count);
+            let module = modules
+                .iter()
+                .find(|m| m.version_meta_data.name == name)
+                .unwrap();
+            let path = module.path.clone();
+            let sender = sender.clone();
+
+            print_tips_msg!(
+                "开始构建 {} ({}), 线程数 {}",
+                name,
+                path.display(),
+                module_jobs
+            );
+            started.insert(name.clone());
+            used_jobs += module_jobs;
+            thread::spawn(move || {
+                let start = Instant::now();
+                let ok = build_module(&path, module_jobs as u32);
+                sender
+                    .send((name, ok, start.elapsed(), module_jobs))
+                    .unwrap();
+            });
+        }
+
+        if used_jobs == 0 {
+            break;
+        }
+
+        let (name, ok, elapsed, module_jobs) = receiver.recv().unwrap();
+        used_jobs -= module_jobs;
+        if ok {
+            print_info_msg!("{} 构建完成, 耗时 {:.1}s", name, elapsed.as_secs_f64());
+            results.insert(name, (BuildStatus::Success, elapsed));
+        } else {
+            print_error_msg!("{} 构建失败", name);
+            results.insert(name, (BuildStatus::Failed, elapsed));
         }
     }
 
+    // 按构建顺序输出构建结果
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["ID", "Name", "Version", "Path", "Result", "Elapsed"]);
+    for (index, name) in order.iter().enumerate() {
+        let module = modules
+            .iter()
+            .find(|m| &m.version_meta_data.name == name)
+            .unwrap();
+        let (status, elapsed) = results[name];
+        table.add_row(vec![
+            (index + 1).to_string(),
+            name.to_string(),
+            module.version_meta_data.version.clone(),
+            module.path.display().to_string(),
+            status.as_str().to_string(),
+            format!("{:.1}s", elapsed.as_secs_f64()),
+        ]);
+    }
+    print_info_msg!("{}", table.to_string());
 
+    if results
+        .values()
+        .any(|(status, _)| *status != BuildStatus::Success)
+    {
+        print_error_msg!("工作空间构建失败");
+        std::process::exit(1);
+    }
 }
 
-fn create(name: &str, no_demo: bool) {
-    print_info_msg!("创建 {}, demo: {}", name, no_demo);
+/// 将当前模块打包为指定格式的软件包
+/// # Arguments
+/// * `format` - 软件包格式
+/// * `install_dir` - 模块的安装目录树
+/// * `output` - 软件包的输出目录
+fn package(format: &str, install_dir: &str, output: &str) {
+    let metadata = match common::read_version_meta_data(Path::new(
+        common::module_constants::MODULE_META_DATA_NAME,
+    )) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if metadata.name.is_empty() || metadata.version.is_empty() {
+        print_error_msg!("模块名称和版本号不能为空, 请先执行 module config set 设置");
+        std::process::exit(1);
+    }
+    if let Err(err) = version::parse_version(&metadata.version) {
+        print_error_msg!("{}", err);
+        std::process::exit(1);
+    }
+
+    print_info_msg!("正在打包 {} {}...", metadata.name, metadata.version);
+    let result = match format {
+        "deb" => deb::write_deb(
+            &metadata,
+            Path::new(install_dir),
+            Path::new(common::module_constants::PACKAGE_SCRIPTS_DIR),
+            Path::new(output),
+        ),
+        "run" => run::write_run(
+            &metadata,
+            Path::new(install_dir),
+            Path::new(common::module_constants::PACKAGE_SCRIPTS_DIR),
+            Path::new(output),
+        ),
+        _ => Err(format!("不支持的软件包格式: {}", format)),
+    };
+
+    match result {
+        Ok(package_path) => {
+            print_info_msg!("软件包制作完成: {}", package_path.display());
+        }
+        Err(err) => {
+            print_error_msg!("软件包制作失败: {}", err);
+            std::process::exit(1);
+        }
+    }
 }
 
-fn build(jobs: &u32) {
-    print_info_msg!("{}", jobs);
+/// 递增当前模块的版本号, 并按需创建 git 标签
+/// # Arguments
+/// * `part` - 递增的部分, major/minor/patch/pre
+/// * `pre_id` - 预发布标识
+/// * `tag` - 是否创建 git 标签
+fn bump_current_module_version(part: &str, pre_id: &str, tag: bool) {
+    let meta_path = Path::new(common::module_constants::MODULE_META_DATA_NAME);
+    let mut metadata = match common::read_version_meta_data(meta_path) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let bumped = match version::parse_version(&metadata.version)
+        .and_then(|current| version::bump_version(&current, part, pre_id))
+    {
+        Ok(bumped) => bumped,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let previous = std::mem::replace(&mut metadata.version, bumped.to_string());
+    if let Err(err) = common::write_version_meta_data(meta_path, &metadata) {
+        print_error_msg!("{}", err);
+        std::process::exit(1);
+    }
+    print_info_msg!("{}: {} -> {}", metadata.name, previous, metadata.version);
+
+    if tag {
+        let tag_name = format!("{}-v{}", metadata.name, metadata.version);
+        let message = format!("{} {}", metadata.name, metadata.version);
+        if common::system("git", vec!["tag", "-a", &tag_name, "-m", &message]) {
+            print_info_msg!("已创建 git 标签 {}", tag_name);
+        } else {
+            print_error_msg!("git 标签 {} 创建失败", tag_name);
+            std::process::exit(1);
+        }
+    }
 }
 
 /// 设置当前模块的配置信息
-fn set_current_module_config(
-    name: &Option<String>,
-    version: &Option<String>,
-    architecture: &Option<String>,
-    author: &Option<String>,
-    description: &Option<String>,
-) {
+fn set_current_module_config(args: &subcommand_define::ConfigSetArgs) {
     // 检查文件是否存在
-    let mut metadata = if Path::new(common::module_constants::MODULE_META_DATA_NAME).exists() {
-        // 文件存在，读取现有内容
-        let mut contents = String::new();
-        File::open(common::module_constants::MODULE_META_DATA_NAME)
-            .unwrap()
-            .read_to_string(&mut contents)
-            .unwrap();
-        toml::from_str(&contents).unwrap_or_default()
+    let meta_path = Path::new(common::module_constants::MODULE_META_DATA_NAME);
+    let mut metadata = if meta_path.exists() {
+        // 文件存在，读取现有内容, 旧的格式在写回时升级, 解析失败时不覆盖原文件
+        match schema::load_version_meta_data(meta_path) {
+            Ok((metadata, notes)) => {
+                for note in notes {
+                    print_tips_msg!("{} 已升级, {}", meta_path.display(), note);
+                }
+                metadata
+            }
+            Err(err) => {
+                print_error_msg!("{}", err);
+                print_tips_msg!("请执行 waytous module config validate 查看详细信息");
+                std::process::exit(1);
+            }
+        }
     } else {
         // 文件不存在，创建新的 VersionMetaData 实例
         common::VersionMetaData::default()
     };
 
     // 更新元数据字段
-    if let Some(name) = name {
+    if let Some(name) = &args.name {
+        if let Err(err) = schema::validate_module_name(name) {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
         metadata.name = name.clone();
     }
-    if let Some(version) = version {
+    if let Some(version) = &args.version {
+        if let Err(err) = version::parse_version(version) {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
         metadata.version = version.clone();
     }
-    if let Some(architecture) = architecture {
+    if let Some(architecture) = &args.architecture {
         metadata.platform = architecture.clone();
     }
-    if let Some(author) = author {
+    if let Some(author) = &args.author {
         metadata.author = author.clone();
     }
-    if let Some(description) = description {
+    if let Some(description) = &args.description {
         metadata.description = description.clone();
     }
 
-    // 序列化为 TOML 格式
-    let toml_metadata = toml::to_string(&metadata).unwrap();
+    // 更新依赖字段, 写入前检查依赖声明格式, 系统依赖使用 Debian 格式
+    type CheckSpecs = fn(&[String]) -> Result<(), String>;
+    let dependency_fields: [(_, _, CheckSpecs); 3] = [
+        (&args.depends, &mut metadata.depends, |specs| {
+            depends::parse_dependencies(specs).map(|_| ())
+        }),
+        (
+            &args.system_depends,
+            &mut metadata.system_depends,
+            depends::check_system_dependencies,
+        ),
+        (&args.conflicts, &mut metadata.conflicts, |specs| {
+            depends::parse_dependencies(specs).map(|_| ())
+        }),
+    ];
+    for (specs, field, check) in dependency_fields {
+        if let Some(specs) = specs {
+            let specs: Vec<String> = specs
+                .iter()
+                .filter(|s| !s.trim().is_empty())
+                .cloned()
+                .collect();
+            if let Err(err) = check(&specs) {
+                print_error_msg!("{}", err);
+                std::process::exit(1);
+            }
+            *field = specs;
+        }
+    }
+
+    // 更新发布信息字段, 地址与时间在写入前检查格式
+    for (url, field) in [
+        (&args.homepage, &mut metadata.homepage),
+        (&args.repository, &mut metadata.repository),
+    ] {
+        if let Some(url) = url {
+            if let Err(err) = schema::validate_url(url) {
+                print_error_msg!("{}", err);
+                std::process::exit(1);
+            }
+            *field = url.clone();
+        }
+    }
+    if let Some(build_time) = &args.build_time {
+        if !build_time.is_empty() && chrono::DateTime::parse_from_rfc3339(build_time).is_err() {
+            print_error_msg!("build_time {} 不是 RFC 3339 格式的时间", build_time);
+            std::process::exit(1);
+        }
+        metadata.build_time = build_time.clone();
+    }
+    if let Some(license) = &args.license {
+        metadata.license = license.clone();
+    }
+    if let Some(git_commit) = &args.git_commit {
+        metadata.git_commit = git_commit.clone();
+    }
+    for (values, field) in [
+        (&args.maintainers, &mut metadata.maintainers),
+        (&args.tags, &mut metadata.tags),
+    ] {
+        if let Some(values) = values {
+            *field = values
+                .iter()
+                .filter(|s| !s.trim().is_empty())
+                .cloned()
+                .collect();
+        }
+    }
 
     // 写入文件
-    let mut meta_file = File::create(common::module_constants::MODULE_META_DATA_NAME).unwrap();
-    meta_file.write_all(toml_metadata.as_bytes()).unwrap();
+    if let Err(err) = common::write_version_meta_data(meta_path, &metadata) {
+        print_error_msg!("{}", err);
+        std::process::exit(1);
+    }
 }
 
 /// 获取当前模块的配置信息
-fn print_current_module_config() {
-    if Path::new(common::module_constants::MODULE_META_DATA_NAME).exists() {
-        let mut contents = String::new();
-        File::open(common::module_constants::MODULE_META_DATA_NAME)
-            .unwrap()
-            .read_to_string(&mut contents)
-            .unwrap();
-        let metadata: common::VersionMetaData = toml::from_str(&contents).unwrap();
+/// # Arguments
+/// * `output` - 输出格式
+fn print_current_module_config(output: &str) {
+    let meta_path = Path::new(common::module_constants::MODULE_META_DATA_NAME);
+    if meta_path.exists() {
+        let metadata = match common::read_version_meta_data(meta_path) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                print_error_msg!("{}", err);
+                print_tips_msg!("请执行 waytous module config validate 查看详细信息");
+                std::process::exit(1);
+            }
+        };
+        if !output::is_table(output) {
+            output::print(output, "module", &metadata);
+            return;
+        }
         let mut table = Table::new();
         table
             .load_preset(UTF8_FULL)
             .apply_modifier(UTF8_ROUND_CORNERS)
             .set_content_arrangement(ContentArrangement::Dynamic)
-            .set_header(vec!["模块名称", "版本号", "平台", "作者", "描述"])
+            .set_header(vec!["字段", "值"])
+            .add_row(vec!["模块名称".to_string(), metadata.name])
+            .add_row(vec!["版本号".to_string(), metadata.version])
+            .add_row(vec!["平台".to_string(), metadata.platform])
+            .add_row(vec!["作者".to_string(), metadata.author])
+            .add_row(vec!["描述".to_string(), metadata.description])
+            .add_row(vec!["依赖".to_string(), metadata.depends.join("\n")])
             .add_row(vec![
-                metadata.name,
-                metadata.version,
-                metadata.platform,
-                metadata.author,
-                metadata.description,
-            ]);
+                "系统依赖".to_string(),
+                metadata.system_depends.join("\n"),
+            ])
+            .add_row(vec!["冲突".to_string(), metadata.conflicts.join("\n")])
+            .add_row(vec!["许可证".to_string(), metadata.license])
+            .add_row(vec!["维护者".to_string(), metadata.maintainers.join("\n")])
+            .add_row(vec!["主页".to_string(), metadata.homepage])
+            .add_row(vec!["源码仓库".to_string(), metadata.repository])
+            .add_row(vec!["标签".to_string(), metadata.tags.join(", ")])
+            .add_row(vec!["Git 提交".to_string(), metadata.git_commit])
+            .add_row(vec!["构建时间".to_string(), metadata.build_time]);
         print_info_msg!("{}", table.to_string());
     } else {
         print_error_msg!(
@@ -199,7 +794,610 @@ fn print_current_module_config() {
     }
 }
 
+/// 校验模块元数据文件的格式, 存在错误时以非 0 状态码退出
+/// # Arguments
+/// * `file` - 元数据文件路径
+/// * `output` - 输出格式
+fn validate_module_config(file: &str, output: &str) {
+    let contents = match std::fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(err) => {
+            print_error_msg!("读取 {} 失败: {}", file, err);
+            std::process::exit(1);
+        }
+    };
+    let issues = schema::validate_version_meta_data(&contents);
+    let errors = issues.iter().filter(|issue| issue.level == "error").count();
+
+    if !output::is_table(output) {
+        output::print(output, "issues", &issues);
+    } else {
+        for issue in &issues {
+            let message = format!(
+                "{}:{}:{}: {}",
+                file, issue.line, issue.column, issue.message
+            );
+            if issue.level == "error" {
+                print_error_msg!("{}", message);
+            } else {
+                print_warning_msg!("{}", message);
+            }
+        }
+        if errors == 0 {
+            print_info_msg!("{} 校验通过", file);
+            // 旧的格式只给出提示, 执行 config set 时自动升级
+            if let Ok(metadata) = toml::from_str::<common::VersionMetaData>(&contents) {
+                if metadata.schema_version < schema::CURRENT_SCHEMA_VERSION {
+                    print_tips_msg!(
+                        "{} 的格式版本为 {}, 执行 waytous module config set 时会自动升级到 {}",
+                        file,
+                        metadata.schema_version,
+                        schema::CURRENT_SCHEMA_VERSION
+                    );
+                }
+            }
+        }
+    }
+
+    if errors != 0 {
+        std::process::exit(1);
+    }
+}
+
 /// 获取指定模块的文件信息
-fn print_installed_module_files(name: &str) {
-    print_debug_msg!("{}", name)
+/// # Arguments
+/// * `name` - 模块名称
+/// * `tree` - 是否以目录树的形式显示
+/// * `output` - 输出格式, 非表格格式时忽略 tree
+fn print_installed_module_files(root: &Path, name: &str, tree: bool, output: &str) {
+    check_module_name(name);
+    let module_dir = common::get_module_active_dir(root, name);
+    if !module_dir.is_dir() {
+        print_error_msg!("模块 {} 未安装", name);
+        std::process::exit(1);
+    }
+    let details = match manifest::scan_module_files(&module_dir) {
+        Ok(details) => details,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if !output::is_table(output) {
+        output::print(output, "files", &details);
+        return;
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Path", "Size", "Mode", "Owner", "Modified", "SHA-256"]);
+
+    let prefixes = if tree {
+        table.add_row(vec![module_dir.display().to_string()]);
+        tree_prefixes(&details)
+    } else {
+        Vec::new()
+    };
+    for (index, detail) in details.iter().enumerate() {
+        let (file_type, size) = match detail.kind {
+            manifest::FileKind::Directory => ('d', "".to_string()),
+            manifest::FileKind::File => ('-', common::format_size(detail.size)),
+            manifest::FileKind::Symlink => ('l', "".to_string()),
+        };
+        let mut path = if tree {
+            prefixes[index].clone() + detail.path.rsplit('/').next().unwrap_or("")
+        } else {
+            detail.path.clone()
+        };
+        if detail.kind == manifest::FileKind::Symlink {
+            path = format!("{} -> {}", path, detail.link);
+        }
+        table.add_row(vec![
+            path,
+            size,
+            common::format_mode(file_type, detail.mode),
+            format!(
+                "{}:{}",
+                common::user_name(detail.uid),
+                common::group_name(detail.gid)
+            ),
+            common::format_timestamp(detail.mtime),
+            detail.sha256.clone(),
+        ]);
+    }
+    print_info_msg!("{}", table.to_string());
+}
+
+/// 生成目录树中每个文件的前缀, ex: "│   ├── "
+/// # Arguments
+/// * `details` - 按目录树顺序排列的文件列表
+/// # Return
+/// * 与 details 一一对应的前缀
+fn tree_prefixes(details: &[manifest::FileDetail]) -> Vec<String> {
+    // 从后向前遍历, 同一目录下最先遇到的文件即为该目录的最后一个文件
+    let mut seen_parents = HashSet::new();
+    let mut is_last = vec![false; details.len()];
+    for (index, detail) in details.iter().enumerate().rev() {
+        let parent = detail
+            .path
+            .rsplit_once('/')
+            .map_or("", |(parent, _)| parent);
+        is_last[index] = seen_parents.insert(parent);
+    }
+
+    // 从前向后遍历, ancestors 记录从根目录到当前文件的每一层是否为所在目录的最后一个文件
+    let mut ancestors: Vec<bool> = Vec::new();
+    details
+        .iter()
+        .zip(is_last)
+        .map(|(detail, last)| {
+            let depth = detail.path.split('/').count();
+            ancestors.resize(depth - 1, true);
+            let mut prefix: String = ancestors
+                .iter()
+                .map(|last| if *last { "    " } else { "│   " })
+                .collect();
+            prefix.push_str(if last { "└── " } else { "├── " });
+            ancestors.push(last);
+            prefix
+        })
+        .collect()
+}
+
+/// 卸载已安装的模块
+/// 卸载前停止模块的进程并备份模块, 有其他模块依赖该模块时需要指定 force
+/// # Arguments
+/// * `name` - 模块名称
+/// * `force` - 是否忽略依赖关系强制卸载
+fn remove(root: &Path, name: &str, force: bool) {
+    check_module_name(name);
+    let module_dir = common::get_module_root_dir(root, name);
+    if !module_dir.is_dir() {
+        print_error_msg!("模块 {} 未安装", name);
+        std::process::exit(1);
+    }
+
+    // 检查是否有其他已安装的模块依赖该模块
+    let dependents: Vec<String> = common::get_installed_module_list(root)
+        .into_iter()
+        .filter(|module| module != name)
+        .filter(|module| {
+            common::get_installed_module_info(root, module)
+                .ok()
+                .and_then(|info| depends::parse_dependencies(&info.version_meta_data.depends).ok())
+                .is_some_and(|module_depends| module_depends.iter().any(|d| d.name == name))
+        })
+        .collect();
+    if !dependents.is_empty() {
+        if force {
+            print_warning_msg!("以下模块依赖 {}: {}", name, dependents.join(", "));
+        } else {
+            print_error_msg!(
+                "以下模块依赖 {}: {}, 如需卸载请使用 --force",
+                name,
+                dependents.join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+
+    supervisord::stop(root, name);
+
+    // 卸载前备份模块的每一个已安装版本
+    let legacy = common::is_legacy_module_layout(root, name);
+    let module_dirs = installed_module_dirs(root, name);
+    for (module_version, version_dir) in &module_dirs {
+        match backup::backup_module(root, name, module_version, version_dir) {
+            Ok(backup_path) => print_info_msg!("已备份 {} 到 {}", name, backup_path.display()),
+            Err(err) => {
+                print_error_msg!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // deb 软件包总是安装到默认的根目录, 其他根目录下的模块与 dpkg 数据库无关
+    let dpkg_root = root.canonicalize().is_ok_and(|root| {
+        Path::new(common::module_constants::DEFAULT_ROOT)
+            .canonicalize()
+            .is_ok_and(|default_root| default_root == root)
+    });
+    let mut result = Ok(());
+    for (module_version, version_dir) in &module_dirs {
+        if result.is_err() {
+            break;
+        }
+        // 旧的单版本布局使用不带版本号的软件包名称
+        let package_name = if legacy {
+            deb::debian_package_name(name)
+        } else {
+            deb::debian_versioned_package_name(name, module_version)
+        };
+        if dpkg_root && is_dpkg_installed(&package_name) {
+            // 通过 deb 安装的版本交给 dpkg 卸载, 保持 dpkg 数据库一致
+            if !common::system("dpkg", vec!["--remove", &package_name]) {
+                result = Err(format!("dpkg 卸载 {} 失败", package_name));
+            }
+        } else if version_dir.exists() {
+            result = remove_module_files(version_dir);
+        }
+    }
+    if result.is_ok() && !legacy {
+        let _ = std::fs::remove_file(module_dir.join(common::module_constants::CURRENT_LINK_NAME));
+        let _ = std::fs::remove_dir(&module_dir);
+    }
+
+    let versions: Vec<&str> = module_dirs.iter().map(|(v, _)| v.as_str()).collect();
+    match result {
+        Ok(()) => print_info_msg!("{} {} 已卸载", name, versions.join(", ")),
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// 获取模块每个已安装版本的版本号与安装目录, 旧的单版本布局下只有一个版本
+fn installed_module_dirs(root: &Path, name: &str) -> Vec<(String, PathBuf)> {
+    if common::is_legacy_module_layout(root, name) {
+        let metadata = common::get_installed_module_info(root, name)
+            .map(|info| info.version_meta_data)
+            .unwrap_or_default();
+        let module_version = if metadata.version.is_empty() {
+            "0.0.0".to_string()
+        } else {
+            metadata.version
+        };
+        return vec![(module_version, common::get_module_root_dir(root, name))];
+    }
+
+    common::get_installed_module_version_list(root, name)
+        .into_iter()
+        .map(|module_version| {
+            let version_dir = common::get_module_version_dir(root, name, &module_version);
+            (module_version, version_dir)
+        })
+        .collect()
+}
+
+/// 检查 deb 软件包是否已安装
+fn is_dpkg_installed(package_name: &str) -> bool {
+    common::system_ret("dpkg-query", vec!["-W", "-f", "${Status}", package_name])
+        .contains("install ok installed")
+}
+
+/// 删除模块的文件, 有文件清单时只删除清单中的文件, 否则删除整个安装目录
+fn remove_module_files(module_dir: &Path) -> Result<(), String> {
+    let module_manifest = match manifest::read_manifest(module_dir) {
+        Ok(module_manifest) => module_manifest,
+        Err(_) => {
+            return std::fs::remove_dir_all(module_dir)
+                .map_err(|e| format!("删除 {} 失败: {}", module_dir.display(), e));
+        }
+    };
+
+    for entry in &module_manifest.files {
+        let path = module_dir.join(&entry.path);
+        if path.symlink_metadata().is_ok() {
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("删除 {} 失败: {}", path.display(), e))?;
+        }
+    }
+    std::fs::remove_file(manifest::manifest_path(module_dir))
+        .map_err(|e| format!("删除文件清单失败: {}", e))?;
+
+    // 从最深的目录开始删除空目录, 非空目录说明存在清单之外的文件
+    for entry in walkdir::WalkDir::new(module_dir)
+        .contents_first(true)
+        .into_iter()
+        .flatten()
+    {
+        if entry.file_type().is_dir() {
+            let _ = std::fs::remove_dir(entry.path());
+        }
+    }
+    if module_dir.exists() {
+        print_warning_msg!("{} 中存在不属于模块的文件, 已保留", module_dir.display());
+    }
+    Ok(())
+}
+
+/// 从备份中恢复模块, 恢复后重新启动之前在运行的进程
+/// # Arguments
+/// * `name` - 模块名称
+/// * `module_version` - 需要恢复的版本号, 为空时恢复最近一次的备份
+/// * `force` - 模块已安装时是否替换, 替换前备份已安装的版本
+fn rollback(root: &Path, name: &str, module_version: &Option<String>, force: bool) {
+    check_module_name(name);
+    let backups = match backup::list_module_backups(root, name) {
+        Ok(backups) => backups,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let selected = match module_version {
+        Some(module_version) => backups.iter().find(|b| &b.version == module_version),
+        None => backups.first(),
+    };
+    let Some(selected) = selected else {
+        print_error_msg!("没有找到 {} 的备份", name);
+        std::process::exit(1);
+    };
+
+    // 旧的单版本布局需要整体替换, 多版本布局只替换同一版本的目录
+    let legacy = common::is_legacy_module_layout(root, name);
+    let replaced_dir = if legacy {
+        common::get_module_root_dir(root, name)
+    } else {
+        common::get_module_version_dir(root, name, &selected.version)
+    };
+    let was_running;
+    // 当前版本的备份与要恢复的备份同名时, 要恢复的备份先移到这里, 恢复后删除
+    let mut moved_backup = None;
+    if replaced_dir.exists() {
+        if !force {
+            print_error_msg!(
+                "模块 {} {} 已安装, 如需替换请使用 --force",
+                name,
+                selected.version
+            );
+            std::process::exit(1);
+        }
+
+        // 替换前备份当前版本, 保证回滚操作本身可以撤销
+        was_running = supervisord::stop(root, name);
+        let current_version = if legacy {
+            common::get_installed_module_info(root, name)
+                .map(|info| info.version_meta_data.version)
+                .unwrap_or_default()
+        } else {
+            selected.version.clone()
+        };
+        if current_version == selected.version {
+            let moved = selected.path.with_extension("restoring");
+            if let Err(err) = std::fs::rename(&selected.path, &moved) {
+                print_error_msg!("移动备份 {} 失败: {}", selected.path.display(), err);
+                std::process::exit(1);
+            }
+            moved_backup = Some(backup::ModuleBackup {
+                version: selected.version.clone(),
+                path: moved,
+                mtime: selected.mtime,
+            });
+        }
+        match backup::backup_module(root, name, &current_version, &replaced_dir) {
+            Ok(backup_path) => print_info_msg!("已备份 {} 到 {}", name, backup_path.display()),
+            Err(err) => {
+                if let Some(moved) = &moved_backup {
+                    let _ = std::fs::rename(&moved.path, &selected.path);
+                }
+                print_error_msg!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        if let Err(err) = std::fs::remove_dir_all(&replaced_dir) {
+            print_error_msg!("删除 {} 失败: {}", replaced_dir.display(), err);
+            std::process::exit(1);
+        }
+    } else {
+        was_running = supervisord::stop(root, name);
+    }
+
+    let version_dir = common::get_module_version_dir(root, name, &selected.version);
+    let source = moved_backup.as_ref().unwrap_or(selected);
+    let result = backup::restore_module(source, &version_dir)
+        .and_then(|_| common::activate_module_version(root, name, &selected.version));
+    match result {
+        Ok(()) => {
+            if let Some(moved) = &moved_backup {
+                let _ = std::fs::remove_file(&moved.path);
+            }
+            print_info_msg!(
+                "已从 {} 恢复 {} {}",
+                selected.path.display(),
+                name,
+                selected.version
+            )
+        }
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    // 回滚前在运行的程序使用恢复后的版本重新启动
+    if was_running {
+        supervisord::start(root, name);
+    }
+}
+
+/// 切换模块当前生效的版本, 切换前停止模块的进程, 切换后重新启动之前在运行的进程
+/// # Arguments
+/// * `name` - 模块名称
+/// * `module_version` - 需要切换到的版本号
+fn activate(root: &Path, name: &str, module_version: &str) {
+    check_module_name(name);
+    if common::is_legacy_module_layout(root, name) {
+        print_error_msg!(
+            "模块 {} 为旧的单版本布局, 不支持切换版本, 请重新安装该模块",
+            name
+        );
+        std::process::exit(1);
+    }
+    if common::get_module_active_version(root, name).as_deref() == Some(module_version) {
+        print_info_msg!("{} {} 已经是当前版本", name, module_version);
+        return;
+    }
+    if !common::get_installed_module_version_list(root, name).contains(&module_version.to_string())
+    {
+        print_error_msg!(
+            "模块 {} 未安装版本 {}, 已安装的版本: {}",
+            name,
+            module_version,
+            common::get_installed_module_version_list(root, name).join(", ")
+        );
+        std::process::exit(1);
+    }
+
+    let was_running = supervisord::stop(root, name);
+    let result = common::activate_module_version(root, name, module_version);
+    match &result {
+        Ok(()) => print_info_msg!("{} 已切换到 {}", name, module_version),
+        Err(err) => print_error_msg!("{}", err),
+    }
+    // 切换前在运行的程序重新启动, 切换失败时 current 未改变, 仍启动原来的版本
+    if was_running {
+        supervisord::start(root, name);
+    }
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
+
+/// 模块校验结果中的一条记录
+#[derive(Serialize)]
+struct VerifyRecord {
+    /// 模块名称
+    name: String,
+
+    /// 校验状态, ok/unknown/error 或文件的差异类型
+    status: String,
+
+    /// 存在差异的文件路径
+    path: String,
+
+    /// 差异描述
+    detail: String,
+}
+
+/// 校验已安装模块的文件完整性, 任意模块校验失败时以非 0 状态码退出
+/// # Arguments
+/// * `name` - 模块名称, 为空时校验所有已安装的模块
+/// * `output` - 输出格式
+fn verify_installed_modules(root: &Path, name: &Option<String>, output: &str) {
+    let modules = match name {
+        Some(name) => {
+            check_module_name(name);
+            vec![name.clone()]
+        }
+        None => common::get_installed_module_list(root),
+    };
+
+    let mut records: Vec<VerifyRecord> = Vec::new();
+    let mut record = |name: &str, status: &str, path: &str, detail: &str| {
+        records.push(VerifyRecord {
+            name: name.to_string(),
+            status: status.to_string(),
+            path: path.to_string(),
+            detail: detail.to_string(),
+        })
+    };
+
+    let mut failed = 0;
+    for module in &modules {
+        let module_dir = common::get_module_active_dir(root, module);
+        if !module_dir.is_dir() {
+            failed += 1;
+            record(module, "error", "", "模块未安装");
+            continue;
+        }
+        // 没有文件清单的模块无法校验, 仅给出提示
+        if !manifest::manifest_path(&module_dir).exists() {
+            record(module, "unknown", "", "没有文件清单, 无法校验");
+            continue;
+        }
+
+        match manifest::verify_module(&module_dir) {
+            Ok(diffs) if diffs.is_empty() => {
+                record(module, "ok", "", "");
+            }
+            Ok(diffs) => {
+                failed += 1;
+                for diff in &diffs {
+                    record(module, diff.kind.as_str(), &diff.path, &diff.detail);
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                record(module, "error", "", &err);
+            }
+        }
+    }
+
+    if !output::is_table(output) {
+        output::print(output, "modules", &records);
+        if failed != 0 {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Name", "Status", "Path", "Detail"]);
+    for record in &records {
+        table.add_row(vec![
+            &record.name,
+            &record.status,
+            &record.path,
+            &record.detail,
+        ]);
+    }
+
+    if failed == 0 {
+        print_info_msg!("{}", table.to_string());
+        print_info_msg!("共校验 {} 个模块, 全部通过", modules.len());
+    } else {
+        print_error_msg!("{}", table.to_string());
+        print_error_msg!("共校验 {} 个模块, {} 个未通过", modules.len(), failed);
+        std::process::exit(1);
+    }
+}
+
+/// 将指定模块的文件与安装时记录的文件清单进行比较
+/// # Arguments
+/// * `name` - 模块名称
+/// * `output` - 输出格式
+fn check_installed_module_files(root: &Path, name: &str, output: &str) {
+    check_module_name(name);
+    let module_dir = common::get_module_active_dir(root, name);
+    let diffs = match manifest::verify_module(&module_dir) {
+        Ok(diffs) => diffs,
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if !output::is_table(output) {
+        output::print(output, "files", &diffs);
+        if !diffs.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if diffs.is_empty() {
+        print_info_msg!("{} 的文件与安装清单一致", name);
+        return;
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Status", "Path", "Detail"]);
+    for diff in &diffs {
+        table.add_row(vec![diff.kind.as_str(), &diff.path, &diff.detail]);
+    }
+    print_error_msg!("{}", table.to_string());
+    print_error_msg!("{} 共有 {} 个文件与安装清单不一致", name, diffs.len());
+    std::process::exit(1);
 }
@@ -1,44 +1,707 @@
-use crate::subcommand_define;
-use crate::{common::common, print_debug_msg, print_tips_msg, print_info_msg};
-use pyo3::prelude::*;
-use pyo3::types::IntoPyDict;
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-25
+ * File: supervisord
+ */
 
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use serde::Serialize;
+
+use crate::common::common;
+use crate::common::config::GlobalConfig;
+use crate::common::health::HealthState;
+use crate::common::protocol::{self, Request, Targets};
+use crate::common::{logfile, output, program, schema, supervisor};
 use crate::subcommand_define::SupervisordCmds;
+use crate::{print_error_msg, print_info_msg, print_tips_msg, print_warning_msg};
 
-pub fn supervisord(sub_cmd: &subcommand_define::SupervisordCmds) {
+pub fn supervisord(sub_cmd: &SupervisordCmds, config: &GlobalConfig) {
+    let root = config.root();
     match sub_cmd {
-        SupervisordCmds::Start { all, name } => {
-            start(all, name).expect("TODO: panic message");
+        SupervisordCmds::Daemon {} => {
+            if let Err(err) = supervisor::run(&root) {
+                print_error_msg!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        SupervisordCmds::Ping {} => {
+            ping(&root, config.output());
+        }
+        SupervisordCmds::Start { all, name, group } => {
+            let targets = Targets {
+                names: name.clone(),
+                groups: group.clone(),
+                all: *all,
+            };
+            control(&root, &Request::Start(targets), config.output());
+        }
+        SupervisordCmds::Restart { all, name, group } => {
+            let targets = Targets {
+                names: name.clone(),
+                groups: group.clone(),
+                all: *all,
+            };
+            control(&root, &Request::Restart(targets), config.output());
+        }
+        SupervisordCmds::Stop { all, name, group } => {
+            let targets = Targets {
+                names: name.clone(),
+                groups: group.clone(),
+                all: *all,
+            };
+            control(&root, &Request::Stop(targets), config.output());
+        }
+        SupervisordCmds::List { watch, interval } => {
+            if *watch {
+                watch_list(&root, *interval, config.output());
+            } else {
+                list(&root, config.output());
+            }
+        }
+        SupervisordCmds::Status { name } => {
+            status(&root, name, config.output());
+        }
+        SupervisordCmds::Logs {
+            name,
+            follow,
+            since,
+            grep,
+        } => {
+            logs(
+                &root,
+                name,
+                *follow,
+                since.as_deref(),
+                grep.as_deref(),
+                config.output(),
+            );
+        }
+        SupervisordCmds::Heartbeat { name } => {
+            heartbeat(&root, name);
+        }
+        SupervisordCmds::CheckConfig {} => {
+            check_config(&root, config.output());
         }
-        SupervisordCmds::Restart { all, name } => {}
-        SupervisordCmds::Stop { all, name } => {}
-        SupervisordCmds::List {  } => {}
     }
 }
 
-fn start(all: &bool, name: &str) -> PyResult<()> {
-    println!("ddd");
-    Python::with_gil(|py| {
-        let sys = py.import_bound("sys")?;
-        let version: String = sys.getattr("version")?.extract()?;
+/// 检查 supervisord 是否在运行并显示其信息, 未运行时以非 0 状态码退出
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `output` - 输出格式
+fn ping(root: &Path, output: &str) {
+    let Some(daemon) = send(root, &Request::Ping {}).daemon else {
+        print_error_msg!("supervisord 未返回自身信息");
+        std::process::exit(1);
+    };
+    if !output::is_table(output) {
+        output::print(output, "daemon", &daemon);
+        return;
+    }
+    print_info_msg!(
+        "supervisord 运行中, pid {}, 版本 {}, 协议版本 {}, 已运行 {}, 管理 {} 个程序",
+        daemon.pid,
+        daemon.version,
+        daemon.protocol,
+        common::format_duration(daemon.uptime),
+        daemon.programs
+    );
+}
+
+/// 向 supervisord 发送启动、停止或重启请求并以表格列出每个程序的结果, 有程序失败时以非 0 状态码退出
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `request` - 请求
+/// * `output` - 输出格式
+fn control(root: &Path, request: &Request, output: &str) {
+    let response = send(root, request);
+    let failed = response.results.iter().filter(|result| !result.ok).count();
+
+    if !output::is_table(output) {
+        output::print(output, "results", &response.results);
+    } else if response.results.is_empty() {
+        print_warning_msg!("没有需要操作的程序");
+    } else {
+        let mut table = Table::new();
+        table
+            .load_preset(UTF8_FULL)
+            .apply_modifier(UTF8_ROUND_CORNERS)
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(vec!["Name", "Result", "State", "Message"]);
+        for result in &response.results {
+            table.add_row(vec![
+                result.name.clone(),
+                if result.ok { "ok" } else { "failed" }.to_string(),
+                result
+                    .state
+                    .map(|state| state.to_string())
+                    .unwrap_or_default(),
+                result.message.clone(),
+            ]);
+        }
+        print_info_msg!("{}", table.to_string());
+        if failed == 0 {
+            print_info_msg!("{} 个程序操作成功", response.results.len());
+        } else {
+            print_error_msg!("{}/{} 个程序操作失败", failed, response.results.len());
+        }
+    }
+
+    if failed != 0 {
+        std::process::exit(1);
+    }
+}
+
+/// 列出 supervisord 中所有程序的状态与资源占用
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `output` - 输出格式
+fn list(root: &Path, output: &str) {
+    let response = send(root, &Request::List {});
+    if !output::is_table(output) {
+        output::print(output, "programs", &response.programs);
+        return;
+    }
+    print_info_msg!("{}", list_table(&response.programs));
+}
 
-        let locals = [("os", py.import_bound("os")?)].into_py_dict_bound(py);
-        let code = "os.getenv('USER') or os.getenv('USERNAME') or 'Unknown'";
-        let user: String = py.eval_bound(code, None, Some(&locals))?.extract()?;
+/// 以类似 top 的方式持续刷新程序列表, 按 Ctrl-C 退出
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `interval` - 刷新间隔秒数
+/// * `output` - 输出格式, 只支持表格
+fn watch_list(root: &Path, interval: u64, output: &str) {
+    if !output::is_table(output) {
+        print_error_msg!("--watch 只支持表格输出, 脚本请定期执行 list");
+        std::process::exit(1);
+    }
+    loop {
+        let response = send(root, &Request::List {});
+        // 清屏并将光标移到左上角
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "{}    每 {} 秒刷新, 按 Ctrl-C 退出",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            interval
+        );
+        println!("{}", list_table(&response.programs));
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
 
-        println!("Hello {}, I'm Python {}", user, version);
-        Ok(())
-    })
+/// 程序列表的表格
+fn list_table(programs: &[protocol::ProgramStatus]) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Name",
+            "Groups",
+            "State",
+            "PID",
+            "Uptime",
+            "Restarts",
+            "Exit Code",
+            "CPU%",
+            "RSS",
+            "Threads",
+            "FDs",
+            "Health",
+            "Message",
+        ]);
+    let optional = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+    for program in programs {
+        table.add_row(vec![
+            program.name.clone(),
+            program.groups.join(", "),
+            program.state.to_string(),
+            program.pid.map(|pid| pid.to_string()).unwrap_or_default(),
+            program
+                .pid
+                .map(|_| common::format_duration(program.uptime))
+                .unwrap_or_default(),
+            program.restarts.to_string(),
+            program
+                .exit_code
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+            program
+                .cpu_percent
+                .map(|percent| format!("{:.1}", percent))
+                .unwrap_or_default(),
+            program.rss.map(common::format_size).unwrap_or_default(),
+            optional(program.threads),
+            optional(program.fds),
+            match (program.health, program.health_message.is_empty()) {
+                (HealthState::Healthy, _) | (_, true) => program.health.to_string(),
+                (health, false) => format!("{}: {}", health, program.health_message),
+            },
+            program.message.clone(),
+        ]);
+    }
+    table
 }
 
-fn restart(all: &bool, name: &str) {
+/// 程序的状态与资源限制, 用于 status 的脚本输出
+#[derive(Serialize)]
+struct ProgramDetail {
+    #[serde(flatten)]
+    program: protocol::ProgramStatus,
 
+    resources: Vec<protocol::ResourceStatus>,
 }
 
-fn stop(all: &bool, name: &str) {
+/// 显示单个程序的状态, 以及资源限制的配置值与进程当前的生效值
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+/// * `output` - 输出格式
+fn status(root: &Path, name: &str, output: &str) {
+    let response = send(
+        root,
+        &Request::Status {
+            name: name.to_string(),
+        },
+    );
+    let Some(program) = response.programs.into_iter().next() else {
+        print_error_msg!("supervisord 未返回 {} 的状态", name);
+        std::process::exit(1);
+    };
+    if !output::is_table(output) {
+        let detail = ProgramDetail {
+            program,
+            resources: response.resources,
+        };
+        output::print(output, "program", &detail);
+        return;
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["字段", "值"])
+        .add_row(vec!["名称".to_string(), program.name.clone()])
+        .add_row(vec!["分组".to_string(), program.groups.join(", ")])
+        .add_row(vec!["状态".to_string(), program.state.to_string()])
+        .add_row(vec![
+            "进程号".to_string(),
+            program.pid.map(|pid| pid.to_string()).unwrap_or_default(),
+        ])
+        .add_row(vec![
+            "运行时长".to_string(),
+            program
+                .pid
+                .map(|_| common::format_duration(program.uptime))
+                .unwrap_or_default(),
+        ])
+        .add_row(vec!["重启次数".to_string(), program.restarts.to_string()])
+        .add_row(vec![
+            "退出码".to_string(),
+            program
+                .exit_code
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+        ])
+        .add_row(vec!["健康状态".to_string(), program.health.to_string()])
+        .add_row(vec!["健康检查".to_string(), program.health_message.clone()])
+        .add_row(vec!["说明".to_string(), program.message.clone()]);
+    print_info_msg!("{}", table.to_string());
 
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["资源", "配置值", "生效值"]);
+    for resource in &response.resources {
+        table.add_row(vec![
+            resource.name.clone(),
+            resource.configured.clone(),
+            resource.effective.clone(),
+        ]);
+    }
+    print_info_msg!("{}", table.to_string());
 }
 
-fn list() {
+/// 显示程序的输出日志, 依次读取历史文件与当前文件, 不需要 supervisord 在运行
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+/// * `follow` - 是否持续输出新写入的日志
+/// * `since` - 只显示最近这段时间的日志, ex: 10m
+/// * `grep` - 只显示匹配该通配符的行, 通配符可以匹配行中的任意部分
+/// * `output` - 输出格式, 持续输出时只支持 table
+fn logs(
+    root: &Path,
+    name: &str,
+    follow: bool,
+    since: Option<&str>,
+    grep: Option<&str>,
+    output: &str,
+) {
+    if follow && !output::is_table(output) {
+        print_error_msg!("--follow 只支持 table 输出格式");
+        std::process::exit(1);
+    }
+    // 名称用于拼接日志路径, 不允许包含 / 或以 . 开头
+    if let Err(err) = schema::validate_module_name(name) {
+        print_error_msg!("{}", err);
+        std::process::exit(1);
+    }
+    // 与 grep 一样匹配行中的任意部分
+    let grep = match grep {
+        Some(grep) => match glob::Pattern::new(&format!("*{}*", grep)) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                print_error_msg!("通配符 {} 格式错误: {}", grep, err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let grep = grep.as_ref();
+    let since = match since.map(common::parse_duration).transpose() {
+        Ok(since) => {
+            since.map(|seconds| chrono::Local::now() - chrono::Duration::seconds(seconds as i64))
+        }
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let files = logfile::log_files(root, name);
+    if files.is_empty() && !follow {
+        print_error_msg!(
+            "没有 {} 的日志: {}",
+            name,
+            logfile::log_path(root, name).display()
+        );
+        std::process::exit(1);
+    }
+
+    let matches = |line: &str| {
+        let recent = match (since, logfile::parse_line(line)) {
+            (Some(since), Some(record)) => chrono::DateTime::parse_from_rfc3339(&record.time)
+                .map(|time| time >= since)
+                .unwrap_or(true),
+            _ => true,
+        };
+        recent && grep.is_none_or(|pattern| pattern.matches(line))
+    };
+    let mut lines = Vec::new();
+    for path in &files {
+        match fs::read(path) {
+            Ok(data) => lines.extend(
+                String::from_utf8_lossy(&data)
+                    .lines()
+                    .filter(|line| matches(line))
+                    .map(str::to_string),
+            ),
+            Err(err) => print_warning_msg!("读取 {} 失败: {}", path.display(), err),
+        }
+    }
 
-}
\ No newline at end of file
+    if !output::is_table(output) {
+        let records: Vec<logfile::LogLine> = lines
+            .iter()
+            .filter_map(|line| logfile::parse_line(line))
+            .collect();
+        output::print(output, "lines", &records);
+        return;
+    }
+    for line in &lines {
+        println!("{}", line);
+    }
+    if follow {
+        follow_log(&logfile::log_path(root, name), grep);
+    }
+}
+
+/// 持续输出日志文件中新写入的行, 日志轮转后先读完旧文件, 再从新文件的开头继续读取
+/// # Arguments
+/// * `path` - 日志文件路径
+/// * `grep` - 只显示匹配该通配符的行
+fn follow_log(path: &Path, grep: Option<&glob::Pattern>) {
+    let mut file = fs::File::open(path).ok();
+    if let Some(file) = file.as_mut() {
+        let _ = file.seek(SeekFrom::End(0));
+    }
+    let mut pending = Vec::new();
+    loop {
+        if let Some(file) = file.as_mut() {
+            print_new_lines(file, &mut pending, grep);
+        }
+        let opened = file.as_ref().and_then(|file| file.metadata().ok());
+        match (fs::metadata(path), opened) {
+            (Ok(current), Some(opened)) if current.ino() == opened.ino() => {
+                // 文件被截断后从头读取
+                if let Some(file) = file.as_mut() {
+                    if file
+                        .stream_position()
+                        .is_ok_and(|position| current.len() < position)
+                    {
+                        let _ = file.seek(SeekFrom::Start(0));
+                        pending.clear();
+                    }
+                }
+            }
+            (Ok(_), _) => {
+                // 检查与轮转之间可能还有写入旧文件的行, 切换前再读一次
+                if let Some(old) = file.as_mut() {
+                    print_new_lines(old, &mut pending, grep);
+                    if !pending.is_empty() {
+                        // 旧文件最后不完整的一行不会再有后续内容, 补全后输出
+                        pending.push(b'\n');
+                        print_new_lines(old, &mut pending, grep);
+                    }
+                }
+                file = fs::File::open(path).ok();
+                continue;
+            }
+            (Err(_), _) => {}
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// 从文件的当前位置读取到末尾并输出其中完整的行, 不完整的部分留在 pending 中等待下一次读取
+fn print_new_lines(file: &mut fs::File, pending: &mut Vec<u8>, grep: Option<&glob::Pattern>) {
+    if file.read_to_end(pending).is_err() {
+        return;
+    }
+    let Some(end) = pending.iter().rposition(|byte| *byte == b'\n') else {
+        return;
+    };
+    let complete: Vec<u8> = pending.drain(..=end).collect();
+    for line in String::from_utf8_lossy(&complete).lines() {
+        if grep.is_none_or(|pattern| pattern.matches(line)) {
+            println!("{}", line);
+        }
+    }
+}
+
+/// 向 supervisord 发送心跳, 成功时不输出, 以便程序周期性调用
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+fn heartbeat(root: &Path, name: &str) {
+    let response = send(
+        root,
+        &Request::Heartbeat {
+            name: name.to_string(),
+        },
+    );
+    if let Some(result) = response.results.iter().find(|result| !result.ok) {
+        print_error_msg!("{}", result.message);
+        std::process::exit(1);
+    }
+}
+
+/// 程序定义中的一个问题
+#[derive(Serialize)]
+struct CheckRecord {
+    /// 模块名称
+    module: String,
+
+    /// 程序定义文件路径
+    path: String,
+
+    /// 问题级别, error/warning
+    level: String,
+
+    /// 行号, 从 1 开始
+    line: usize,
+
+    /// 列号, 从 1 开始
+    column: usize,
+
+    /// 问题描述
+    message: String,
+}
+
+/// 校验所有已安装模块的 supervise.toml, 按模块报告问题, 有错误时以非 0 状态码退出
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `output` - 输出格式
+fn check_config(root: &Path, output: &str) {
+    let mut records = Vec::new();
+    let mut checked = Vec::new();
+    let mut contents = BTreeMap::new();
+    let mut modules = common::get_installed_module_list(root);
+    modules.sort();
+    for name in modules {
+        let path = program::program_path(root, &name);
+        if !path.exists() {
+            continue;
+        }
+        checked.push(name.clone());
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                contents.insert(name, text);
+            }
+            Err(err) => records.push(CheckRecord {
+                module: name,
+                path: path.display().to_string(),
+                level: "error".to_string(),
+                line: 1,
+                column: 1,
+                message: format!("读取失败: {}", err),
+            }),
+        }
+    }
+
+    // 先校验每个程序定义本身, 再校验程序之间的依赖关系
+    let mut relations = program::validate_relations(&contents);
+    for (name, text) in &contents {
+        let module_dir = common::get_module_active_dir(root, name);
+        let mut issues = program::validate_program(&module_dir, text);
+        issues.extend(relations.remove(name).unwrap_or_default());
+        issues.sort_by_key(|issue| (issue.line, issue.column));
+        let path = program::program_path(root, name);
+        records.extend(issues.into_iter().map(|issue| CheckRecord {
+            module: name.clone(),
+            path: path.display().to_string(),
+            level: issue.level.to_string(),
+            line: issue.line,
+            column: issue.column,
+            message: issue.message,
+        }));
+    }
+    let failed: Vec<&str> = records
+        .iter()
+        .filter(|record| record.level == "error")
+        .map(|record| record.module.as_str())
+        .collect();
+
+    if !output::is_table(output) {
+        output::print(output, "issues", &records);
+    } else if checked.is_empty() {
+        print_warning_msg!("没有模块提供 supervise.toml");
+    } else {
+        for name in &checked {
+            for record in records.iter().filter(|record| &record.module == name) {
+                let message = format!(
+                    "{}:{}:{}: {}",
+                    record.path, record.line, record.column, record.message
+                );
+                if record.level == "error" {
+                    print_error_msg!("{}", message);
+                } else {
+                    print_warning_msg!("{}", message);
+                }
+            }
+            if !failed.contains(&name.as_str()) {
+                print_info_msg!("{} 校验通过", name);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// 停止模块对应的程序, 供卸载、回滚与切换版本使用, supervisord 未运行时不做处理
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+/// # Return
+/// * true - 停止前程序处于运行中或等待重启, 操作完成后需要重新启动
+/// * false - 程序未运行或不由 supervisord 管理
+pub fn stop(root: &Path, name: &str) -> bool {
+    if !protocol::socket_path(root).exists() {
+        return false;
+    }
+    let status = Request::Status {
+        name: name.to_string(),
+    };
+    let was_running = protocol::request(root, &status).is_ok_and(|response| {
+        response.programs.iter().any(|program| {
+            matches!(
+                program.state,
+                supervisor::ProcessState::Starting
+                    | supervisor::ProcessState::Running
+                    | supervisor::ProcessState::Backoff
+            )
+        })
+    });
+
+    let request = Request::Stop(Targets {
+        names: vec![name.to_string()],
+        ..Default::default()
+    });
+    match protocol::request(root, &request) {
+        // 没有程序定义的模块不由 supervisord 管理, 忽略其失败结果
+        Ok(response) => {
+            for result in response.results.iter().filter(|result| result.ok) {
+                print_info_msg!("{}: {}", result.name, result.message);
+            }
+        }
+        Err(err) => print_warning_msg!("{}", err),
+    }
+    was_running
+}
+
+/// 重新启动被 stop 停止的模块程序, 启动失败时只给出警告, 不影响已完成的操作
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+pub fn start(root: &Path, name: &str) {
+    let request = Request::Start(Targets {
+        names: vec![name.to_string()],
+        ..Default::default()
+    });
+    let results = match protocol::request(root, &request) {
+        Ok(response) => match response.error {
+            Some(err) => Err(err),
+            None => Ok(response.results),
+        },
+        Err(err) => Err(err),
+    };
+    match results {
+        Ok(results) => {
+            for result in &results {
+                if result.ok {
+                    print_info_msg!("{}: {}", result.name, result.message);
+                } else {
+                    print_warning_msg!("{}: {}", result.name, result.message);
+                }
+            }
+        }
+        Err(err) => {
+            print_warning_msg!("重新启动 {} 失败: {}", name, err);
+            print_tips_msg!("请执行 waytous supervisord start --name {} 启动模块", name);
+        }
+    }
+}
+
+/// 发送请求, 无法连接 supervisord 或请求失败时退出
+fn send(root: &Path, request: &Request) -> protocol::Response {
+    match protocol::request(root, request) {
+        Ok(response) => match response.error {
+            Some(err) => {
+                print_error_msg!("{}", err);
+                std::process::exit(1);
+            }
+            None => response,
+        },
+        Err(err) => {
+            print_error_msg!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-21
+ * File: backup
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{Archive, Builder};
+
+use crate::common::{common, schema};
+
+/// 模块的备份文件
+pub struct ModuleBackup {
+    /// 模块版本号
+    pub version: String,
+
+    /// 备份文件路径
+    pub path: PathBuf,
+
+    /// 备份时间戳(秒)
+    pub mtime: i64,
+}
+
+/// 将模块安装目录备份为 <root>/backup/<name>/<version>.tar.gz,
+/// 每个模块的备份位于单独的目录中, 版本号不必符合 SemVer 也能被列出和恢复
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+/// * `module_version` - 模块版本号
+/// * `module_dir` - 模块安装目录
+/// # Return
+/// * Ok(PathBuf) - 备份文件路径
+/// * Err(String) - 备份失败的原因
+pub fn backup_module(
+    root: &Path,
+    name: &str,
+    module_version: &str,
+    module_dir: &Path,
+) -> Result<PathBuf, String> {
+    schema::validate_module_name(name)?;
+    if module_version.is_empty() || module_version.contains('/') || module_version.starts_with('.')
+    {
+        return Err(format!("版本号 {:?} 不能用作备份文件名称", module_version));
+    }
+    let backup_dir = common::get_backup_path(root).join(name);
+    fs::create_dir_all(&backup_dir)
+        .map_err(|e| format!("创建备份目录 {} 失败: {}", backup_dir.display(), e))?;
+
+    let backup_path = backup_dir.join(format!("{}.tar.gz", module_version));
+    let file = fs::File::create(&backup_path)
+        .map_err(|e| format!("创建备份文件 {} 失败: {}", backup_path.display(), e))?;
+    let mut builder = Builder::new(GzEncoder::new(file, Compression::default()));
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(".", module_dir)
+        .and_then(|_| builder.into_inner())
+        .and_then(|encoder| encoder.finish())
+        .map_err(|e| format!("备份 {} 失败: {}", module_dir.display(), e))?;
+
+    Ok(backup_path)
+}
+
+/// 列出指定模块的所有备份, 按备份时间从新到旧排序
+/// # Return
+/// * Ok(Vec<ModuleBackup>) - 模块的备份, 没有备份时为空
+/// * Err(String) - 模块名称无效
+pub fn list_module_backups(root: &Path, name: &str) -> Result<Vec<ModuleBackup>, String> {
+    schema::validate_module_name(name)?;
+    let mut backups: Vec<ModuleBackup> = fs::read_dir(common::get_backup_path(root).join(name))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let module_version = file_name.strip_suffix(".tar.gz")?.to_string();
+            let mtime = entry
+                .metadata()
+                .ok()
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or(0);
+            Some(ModuleBackup {
+                version: module_version,
+                path: entry.path(),
+                mtime,
+            })
+        })
+        .collect();
+
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.mtime));
+    Ok(backups)
+}
+
+/// 将备份文件恢复到模块安装目录
+/// # Arguments
+/// * `backup` - 备份文件
+/// * `module_dir` - 模块安装目录, 必须不存在
+/// # Return
+/// * Ok(()) - 恢复成功
+/// * Err(String) - 恢复失败的原因
+pub fn restore_module(backup: &ModuleBackup, module_dir: &Path) -> Result<(), String> {
+    let file = fs::File::open(&backup.path)
+        .map_err(|e| format!("读取备份文件 {} 失败: {}", backup.path.display(), e))?;
+    fs::create_dir_all(module_dir)
+        .map_err(|e| format!("创建 {} 失败: {}", module_dir.display(), e))?;
+
+    let mut archive = Archive::new(GzDecoder::new(file));
+    archive.set_preserve_permissions(true);
+    archive
+        .unpack(module_dir)
+        .map_err(|e| format!("恢复备份 {} 失败: {}", backup.path.display(), e))
+}
@@ -4,21 +4,35 @@
  * File: common
  */
 
+use std::collections::BTreeMap;
 use std::fs;
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::string::String;
 
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use toml;
+use walkdir::WalkDir;
 
-use crate::common::common;
+use crate::common::schema;
 
 pub mod module_constants {
-    pub const INSTALL_PATH: &str = "/opt/waytous/modules";
+    /// 默认的安装根目录, 模块安装在 <root>/modules, 备份保存在 <root>/backup,
+    /// supervisord 的运行时文件保存在 <root>/run, 程序的输出保存在 <root>/log
+    pub const DEFAULT_ROOT: &str = "/opt/waytous";
+    pub const ROOT_ENV_NAME: &str = "WAYTOUS_ROOT";
+    pub const MODULES_DIR_NAME: &str = "modules";
+    pub const BACKUP_DIR_NAME: &str = "backup";
+    pub const CURRENT_LINK_NAME: &str = "current";
     pub const MODULE_META_DATA_NAME: &str = "version.toml";
+    pub const MODULE_MANIFEST_NAME: &str = "manifest.toml";
+    pub const BUILD_DIR_NAME: &str = "build";
+    pub const INSTALL_DIR_NAME: &str = "install";
+    pub const PACKAGE_SCRIPTS_DIR: &str = "package";
+    pub const RUN_DIR_NAME: &str = "run";
+    pub const LOG_DIR_NAME: &str = "log";
+    pub const SUPERVISE_FILE_NAME: &str = "supervise.toml";
 }
 
 /// 打印 info 信息
@@ -109,17 +123,100 @@ pub struct ModuleInfo {
     pub module_file_num: u64, // 模块文件数量
 
     /// 软件模块版本信息
+    #[serde(flatten)]
     pub version_meta_data: VersionMetaData, // 模块元数据
 }
 
 /// 模块的元数据
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct VersionMetaData {
+    /// 元数据的格式版本号, 用于升级旧的 version.toml
+    #[serde(default = "schema::legacy_schema_version")]
+    pub schema_version: u32,
+
     pub name: String,
     pub version: String,
     pub platform: String,
     pub author: String,
     pub description: String,
+
+    /// 依赖的其他模块, 格式: "name" 或 "name (>= 1.0.0)"
+    #[serde(default)]
+    pub depends: Vec<String>,
+
+    /// 依赖的系统软件包(apt), 格式同 Debian control 文件的 Depends 字段, 打包时原样写入,
+    /// ex: "libfoo (>= 2:1.0-1ubuntu2)"
+    #[serde(default)]
+    pub system_depends: Vec<String>,
+
+    /// 与当前模块冲突的模块, 格式同 depends
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+
+    /// 许可证, SPDX 格式, ex: Apache-2.0
+    #[serde(default)]
+    pub license: String,
+
+    /// 维护者, 格式: "name <email>"
+    #[serde(default)]
+    pub maintainers: Vec<String>,
+
+    /// 项目主页
+    #[serde(default)]
+    pub homepage: String,
+
+    /// 源码仓库地址
+    #[serde(default)]
+    pub repository: String,
+
+    /// 标签, 用于筛选模块, ex: perception
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// 构建时源码的 git 提交, 工作区有未提交的修改时以 -dirty 结尾, 由 module build 记录
+    #[serde(default)]
+    pub git_commit: String,
+
+    /// 构建时间, RFC 3339 格式, 由 module build 记录
+    #[serde(default)]
+    pub build_time: String,
+
+    /// 不认识的字段, 读写时原样保留, 避免旧版本的 cli 丢失新版本添加的字段
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+impl Default for VersionMetaData {
+    fn default() -> Self {
+        VersionMetaData {
+            schema_version: schema::CURRENT_SCHEMA_VERSION,
+            name: String::new(),
+            version: String::new(),
+            platform: String::new(),
+            author: String::new(),
+            description: String::new(),
+            depends: Vec::new(),
+            system_depends: Vec::new(),
+            conflicts: Vec::new(),
+            license: String::new(),
+            maintainers: Vec::new(),
+            homepage: String::new(),
+            repository: String::new(),
+            tags: Vec::new(),
+            git_commit: String::new(),
+            build_time: String::new(),
+            extra: toml::Table::new(),
+        }
+    }
+}
+
+/// 工作空间中的模块
+pub struct WorkspaceModule {
+    /// 模块所在目录
+    pub path: PathBuf,
+
+    /// 模块元数据
+    pub version_meta_data: VersionMetaData,
 }
 
 /// 执行 shell 终端指令 并返回执行状态
@@ -157,10 +254,10 @@ pub fn system_ret(program: &str, parameter: Vec<&str>) -> String {
     let output = Command::new(program).args(&parameter).output();
     match output {
         Ok(output) => {
-            return if output.status.success() {
-                String::from_utf8(output.stdout).unwrap().to_string()
+            if output.status.success() {
+                String::from_utf8(output.stdout).unwrap()
             } else {
-                String::from_utf8(output.stderr).unwrap().to_string()
+                String::from_utf8(output.stderr).unwrap()
             }
         }
         Err(e) => {
@@ -205,74 +302,264 @@ pub fn get_system_info() -> SystemInfo {
 
 /// 根据传入的模块名称，返回该模块的信息
 /// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+/// # Return
+/// ModuleInfo - 模块信息
+pub fn get_installed_module_info(root: &Path, name: &str) -> Result<ModuleInfo, String> {
+    get_module_info_from_dir(&get_module_active_dir(root, name))
+}
+
+/// 根据传入的模块名称和版本号，返回该版本的模块信息
+/// # Arguments
+/// * `root` - 安装根目录
 /// * `name` - 模块名称
+/// * `version` - 模块版本号
 /// # Return
 /// ModuleInfo - 模块信息
-pub fn get_installed_module_info(name: &str) -> Result<ModuleInfo, String> {
-    // 根据传入的模块名称， 组装模块的安装路径
-    let module_install_path = format!("{}/{}", module_constants::INSTALL_PATH, name);
+pub fn get_installed_module_version_info(
+    root: &Path,
+    name: &str,
+    version: &str,
+) -> Result<ModuleInfo, String> {
+    get_module_info_from_dir(&get_module_version_dir(root, name, version))
+}
+
+/// 根据模块的安装目录，返回该目录下的模块信息
+fn get_module_info_from_dir(module_dir: &Path) -> Result<ModuleInfo, String> {
+    // 模块的安装路径
+    let module_install_path = module_dir.to_string_lossy().to_string();
 
-    // 获取传入模块名称的模块元数据文件路径
+    // 获取模块元数据文件路径
     let module_meta_file_path = format!(
-        "{}/{}/{}",
-        module_constants::INSTALL_PATH,
-        name,
+        "{}/{}",
+        module_install_path,
         module_constants::MODULE_META_DATA_NAME
     );
 
     // 检查传入的模块是否存在
-    match fs::metadata(module_install_path) {
+    match fs::metadata(&module_install_path) {
         Ok(_file) => {
             let path = Path::new(&module_meta_file_path);
 
+            // 统计模块包含的文件大小和数量
+            let (module_file_size, module_file_num) =
+                get_directory_usage(Path::new(&module_install_path));
+
             // 检查模块元数据文件是否存在
             if path.is_file() && path.exists() {
-                let mut contents = String::new();
-
-                File::open(module_meta_file_path.to_string())
-                    .unwrap()
-                    .read_to_string(&mut contents)
-                    .unwrap();
-                let metadata: VersionMetaData = toml::from_str(&contents).unwrap();
+                let metadata = read_version_meta_data(path)?;
                 let module_info = ModuleInfo {
-                    install_path: "".to_string(),
-                    module_file_size: 0,
-                    module_file_num: 0,
+                    install_path: module_install_path,
+                    module_file_size,
+                    module_file_num,
                     version_meta_data: metadata,
                 };
                 Ok(module_info)
             } else {
                 let module_info = ModuleInfo {
-                    install_path: "".to_string(),
-                    module_file_size: 0,
-                    module_file_num: 0,
+                    install_path: module_install_path,
+                    module_file_size,
+                    module_file_num,
                     version_meta_data: Default::default(),
                 };
                 Ok(module_info)
             }
         }
-        Err(e) => {
-            return Err(format!("{}", e));
+        Err(e) => Err(format!("{}", e)),
+    }
+}
+
+/// 统计目录下所有普通文件的总大小和数量, 不跟随符号链接
+/// # Arguments
+/// * `path` - 目录路径
+/// # Return
+/// * (u64, u64) - (文件总大小(字节), 文件数量)
+pub fn get_directory_usage(path: &Path) -> (u64, u64) {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .fold((0, 0), |(size, num), metadata| {
+            (size + metadata.len(), num + 1)
+        })
+}
+
+/// 将字节数转换为便于阅读的格式, ex: 1536 -> 1.5 KiB
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// 将时长格式化为易读的形式, ex: 45s, 3m12s, 2h05m, 1d03h
+pub fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d{:02}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+/// 解析时长, 支持 s/m/h/d 单位, 没有单位时为秒, ex: 30s, 10m, 2h, 1d
+/// # Return
+/// * Ok(u64) - 秒数
+/// * Err(String) - 格式错误
+pub fn parse_duration(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => text.split_at(index),
+        None => (text, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("时长 {} 格式错误, ex: 30s, 10m, 2h, 1d", text)),
+    };
+    number
+        .parse::<u64>()
+        .map(|number| number * scale)
+        .map_err(|_| format!("时长 {} 格式错误, ex: 30s, 10m, 2h, 1d", text))
+}
+
+/// 获取受管程序输出的保存目录, ex: /opt/waytous/log
+pub fn get_log_path(root: &Path) -> PathBuf {
+    root.join(module_constants::LOG_DIR_NAME)
+}
+
+/// 获取模块的安装目录, ex: /opt/waytous/modules
+pub fn get_modules_path(root: &Path) -> PathBuf {
+    root.join(module_constants::MODULES_DIR_NAME)
+}
+
+/// 获取模块备份的保存目录, ex: /opt/waytous/backup
+pub fn get_backup_path(root: &Path) -> PathBuf {
+    root.join(module_constants::BACKUP_DIR_NAME)
+}
+
+/// 获取模块的根目录, ex: /opt/waytous/modules/<name>
+pub fn get_module_root_dir(root: &Path, name: &str) -> PathBuf {
+    get_modules_path(root).join(name)
+}
+
+/// 获取模块指定版本的安装目录, ex: /opt/waytous/modules/<name>/<version>
+pub fn get_module_version_dir(root: &Path, name: &str, version: &str) -> PathBuf {
+    get_module_root_dir(root, name).join(version)
+}
+
+/// 获取模块当前生效版本的安装目录
+/// 多版本布局下为 current 指向的 <name>/<version>, 旧的单版本布局下为 <name> 本身
+pub fn get_module_active_dir(root: &Path, name: &str) -> PathBuf {
+    match get_module_active_version(root, name) {
+        Some(version) if get_module_version_dir(root, name, &version).is_dir() => {
+            get_module_version_dir(root, name, &version)
         }
+        _ => get_module_root_dir(root, name),
     }
 }
 
+/// 检查模块是否为旧的单版本布局, 即元数据文件直接位于 <name> 目录下
+pub fn is_legacy_module_layout(root: &Path, name: &str) -> bool {
+    get_module_root_dir(root, name)
+        .join(module_constants::MODULE_META_DATA_NAME)
+        .is_file()
+}
+
+/// 获取模块当前生效的版本号, 旧的单版本布局或未激活任何版本时返回 None
+pub fn get_module_active_version(root: &Path, name: &str) -> Option<String> {
+    fs::read_link(get_module_root_dir(root, name).join(module_constants::CURRENT_LINK_NAME))
+        .ok()
+        .and_then(|link| link.file_name().map(|n| n.to_string_lossy().to_string()))
+}
+
+/// 获取模块已安装的所有版本号, 按版本号从旧到新排序
+/// 旧的单版本布局下返回元数据中的版本号
+pub fn get_installed_module_version_list(root: &Path, name: &str) -> Vec<String> {
+    if is_legacy_module_layout(root, name) {
+        return get_installed_module_info(root, name)
+            .map(|info| vec![info.version_meta_data.version])
+            .unwrap_or_default();
+    }
+
+    let mut versions: Vec<String> = fs::read_dir(get_module_root_dir(root, name))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name() != module_constants::CURRENT_LINK_NAME)
+        .filter(|entry| {
+            entry
+                .path()
+                .join(module_constants::MODULE_META_DATA_NAME)
+                .is_file()
+        })
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    versions.sort_by(|a, b| match (Version::parse(a), Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    });
+    versions
+}
+
+/// 切换模块当前生效的版本, 通过替换 current 符号链接实现
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+/// * `version` - 需要激活的版本号
+/// # Return
+/// * Ok(()) - 切换成功
+/// * Err(String) - 版本未安装或切换失败
+pub fn activate_module_version(root: &Path, name: &str, version: &str) -> Result<(), String> {
+    if !get_module_version_dir(root, name, version)
+        .join(module_constants::MODULE_META_DATA_NAME)
+        .is_file()
+    {
+        return Err(format!("模块 {} 未安装版本 {}", name, version));
+    }
+
+    // 先创建临时链接再重命名, 保证 current 链接的替换是原子的
+    let root_dir = get_module_root_dir(root, name);
+    let temp_link = root_dir.join(format!(".{}.tmp", module_constants::CURRENT_LINK_NAME));
+    let _ = fs::remove_file(&temp_link);
+    std::os::unix::fs::symlink(version, &temp_link)
+        .and_then(|_| {
+            fs::rename(
+                &temp_link,
+                root_dir.join(module_constants::CURRENT_LINK_NAME),
+            )
+        })
+        .map_err(|e| format!("切换 {} 的版本失败: {}", name, e))
+}
+
 /// 获取已经安装的模块列表
-pub fn get_installed_module_list() -> Vec<String> {
+/// # Arguments
+/// * `root` - 安装根目录
+pub fn get_installed_module_list(root: &Path) -> Vec<String> {
     let mut module_list = Vec::new();
 
     // 读取目录的内容
-    if let Ok(entries) = fs::read_dir(common::module_constants::INSTALL_PATH) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                // 检查条目是否是目录
-                if path.is_dir() {
-                    // 获取目录名字并存储到列表中
-                    if let Some(folder_name) = path.file_name() {
-                        if let Some(folder_name_str) = folder_name.to_str() {
-                            module_list.push(folder_name_str.to_string());
-                        }
+    if let Ok(entries) = fs::read_dir(get_modules_path(root)) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // 检查条目是否是目录
+            if path.is_dir() {
+                // 获取目录名字并存储到列表中
+                if let Some(folder_name) = path.file_name() {
+                    if let Some(folder_name_str) = folder_name.to_str() {
+                        module_list.push(folder_name_str.to_string());
                     }
                 }
             }
@@ -281,3 +568,160 @@ pub fn get_installed_module_list() -> Vec<String> {
 
     module_list
 }
+
+/// 将文件权限转换为 ls 风格的字符串, ex: drwxr-xr-x
+pub fn format_mode(file_type: char, mode: u32) -> String {
+    let mut result = String::from(file_type);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        result.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        result.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        result.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    result
+}
+
+/// 将时间戳(秒)转换为本地时间字符串, ex: 2024-08-19 10:30:00
+pub fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// 根据用户 ID 获取用户名, 找不到时返回用户 ID
+pub fn user_name(uid: u32) -> String {
+    lookup_id_name("/etc/passwd", uid)
+}
+
+/// 根据用户组 ID 获取用户组名, 找不到时返回用户组 ID
+pub fn group_name(gid: u32) -> String {
+    lookup_id_name("/etc/group", gid)
+}
+
+/// 根据用户名从 /etc/passwd 中获取用户 ID 与主用户组 ID
+pub fn user_ids(name: &str) -> Option<(u32, u32)> {
+    let contents = fs::read_to_string("/etc/passwd").ok()?;
+    contents.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 4 || fields[0] != name {
+            return None;
+        }
+        Some((fields[2].parse().ok()?, fields[3].parse().ok()?))
+    })
+}
+
+/// 从 /etc/passwd 或 /etc/group 中查找 ID 对应的名称, 两者第三列均为 ID
+fn lookup_id_name(file: &str, id: u32) -> String {
+    fs::read_to_string(file)
+        .ok()
+        .and_then(|contents| {
+            contents.lines().find_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                match fields.get(2).and_then(|field| field.parse::<u32>().ok()) {
+                    Some(line_id) if line_id == id => Some(fields[0].to_string()),
+                    _ => None,
+                }
+            })
+        })
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// 获取已安装模块的版本号
+/// # Arguments
+/// * `root` - 安装根目录
+/// # Return
+/// * BTreeMap<String, String> - key 为模块名称, value 为版本号
+pub fn get_installed_module_versions(root: &Path) -> BTreeMap<String, String> {
+    get_installed_module_list(root)
+        .into_iter()
+        .filter_map(|module| {
+            let meta_path =
+                get_module_active_dir(root, &module).join(module_constants::MODULE_META_DATA_NAME);
+            read_version_meta_data(&meta_path)
+                .ok()
+                .map(|metadata| (module, metadata.version))
+        })
+        .collect()
+}
+
+/// 读取指定路径的模块元数据文件, 旧的格式会在内存中升级到当前格式
+/// # Arguments
+/// * `path` - 元数据文件路径
+/// # Return
+/// * Ok(VersionMetaData) - 模块元数据
+/// * Err(String) - 读取或解析失败的原因
+pub fn read_version_meta_data(path: &Path) -> Result<VersionMetaData, String> {
+    schema::load_version_meta_data(path).map(|(metadata, _)| metadata)
+}
+
+/// 将模块元数据写入指定路径
+/// # Arguments
+/// * `path` - 元数据文件路径
+/// * `metadata` - 模块元数据
+/// # Return
+/// * Ok(()) - 写入成功
+/// * Err(String) - 序列化或写入失败的原因
+pub fn write_version_meta_data(path: &Path, metadata: &VersionMetaData) -> Result<(), String> {
+    let contents = toml::to_string(metadata).map_err(|e| e.to_string())?;
+    fs::write(path, contents).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))
+}
+
+/// 查找工作空间中的所有模块, 即包含 version.toml 的目录
+/// # Arguments
+/// * `root` - 工作空间根目录
+/// # Return
+/// * Ok(Vec<WorkspaceModule>) - 模块列表
+/// * Err(String) - 元数据解析失败或模块名称重复
+pub fn find_workspace_modules(root: &Path) -> Result<Vec<WorkspaceModule>, String> {
+    let mut modules: Vec<WorkspaceModule> = Vec::new();
+
+    // 跳过隐藏目录以及构建产物目录
+    let walker = WalkDir::new(root).into_iter().filter_entry(|entry| {
+        let file_name = entry.file_name().to_string_lossy();
+        entry.depth() == 0
+            || !(file_name.starts_with('.') || file_name == "build" || file_name == "install")
+    });
+
+    for entry in walker.flatten() {
+        if !entry.file_type().is_file()
+            || entry.file_name() != module_constants::MODULE_META_DATA_NAME
+        {
+            continue;
+        }
+
+        let path = entry.path().parent().unwrap_or(root).to_path_buf();
+        let mut version_meta_data = read_version_meta_data(entry.path())?;
+
+        // 未设置模块名称时使用目录名称
+        if version_meta_data.name.is_empty() {
+            version_meta_data.name = path
+                .canonicalize()
+                .ok()
+                .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+                .unwrap_or_default();
+        }
+
+        if let Some(module) = modules
+            .iter()
+            .find(|m| m.version_meta_data.name == version_meta_data.name)
+        {
+            return Err(format!(
+                "模块名称 {} 重复: {} 和 {}",
+                version_meta_data.name,
+                module.path.display(),
+                path.display()
+            ));
+        }
+
+        modules.push(WorkspaceModule {
+            path,
+            version_meta_data,
+        });
+    }
+
+    Ok(modules)
+}
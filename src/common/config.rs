@@ -0,0 +1,217 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-23
+ * File: config
+ */
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::common::common::module_constants;
+
+/// 系统级配置文件的路径
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/waytous/config.toml";
+
+/// 用户级配置文件相对于用户配置目录的路径
+pub const USER_CONFIG_PATH: &str = "waytous/config.toml";
+
+/// 全局配置项的定义
+struct ConfigKey {
+    /// 配置文件中的键名
+    name: &'static str,
+
+    /// 对应的环境变量
+    env: &'static str,
+
+    /// 对应的命令行参数, 为空表示没有对应的命令行参数
+    flag: &'static str,
+
+    /// 默认值, 为空表示未设置
+    default: &'static str,
+
+    /// 允许的取值, 为空表示不限制
+    values: &'static [&'static str],
+}
+
+/// 所有支持的全局配置项
+const CONFIG_KEYS: [ConfigKey; 3] = [
+    ConfigKey {
+        name: "root",
+        env: module_constants::ROOT_ENV_NAME,
+        flag: "--root",
+        default: module_constants::DEFAULT_ROOT,
+        values: &[],
+    },
+    ConfigKey {
+        name: "output",
+        env: "WAYTOUS_OUTPUT",
+        flag: "--output",
+        default: "table",
+        values: &["table", "json", "yaml", "toml", "csv"],
+    },
+    // artifact write module-image 的 --device-type 只作用于该命令, 在命令中覆盖配置的值
+    ConfigKey {
+        name: "device_type",
+        env: "WAYTOUS_DEVICE_TYPE",
+        flag: "",
+        default: "",
+        values: &[],
+    },
+];
+
+/// 生效的配置项
+#[derive(Serialize, Clone)]
+pub struct ConfigEntry {
+    /// 配置项名称
+    pub key: String,
+
+    /// 配置项的值, 为空表示未设置
+    pub value: String,
+
+    /// 配置项的来源, ex: default, /etc/waytous/config.toml, env WAYTOUS_ROOT, --root
+    pub source: String,
+}
+
+/// 合并后的全局配置
+/// 优先级从低到高依次为: 默认值, 系统级配置文件, 用户级配置文件, 环境变量, 命令行参数
+pub struct GlobalConfig {
+    entries: Vec<ConfigEntry>,
+}
+
+impl GlobalConfig {
+    /// 加载全局配置
+    /// # Arguments
+    /// * `flags` - 命令行中指定的配置项, (配置项名称, 值)
+    /// # Return
+    /// * Ok(GlobalConfig) - 合并后的全局配置
+    /// * Err(String) - 配置文件读取或解析失败, 或配置项的值不合法
+    pub fn load(flags: &[(&str, &Option<String>)]) -> Result<Self, String> {
+        let mut entries: Vec<ConfigEntry> = CONFIG_KEYS
+            .iter()
+            .map(|key| ConfigEntry {
+                key: key.name.to_string(),
+                value: key.default.to_string(),
+                source: "default".to_string(),
+            })
+            .collect();
+
+        let mut layers = vec![PathBuf::from(SYSTEM_CONFIG_PATH)];
+        layers.extend(user_config_path());
+        for path in &layers {
+            for (name, value) in read_config_file(path)? {
+                if let Some(entry) = entries.iter_mut().find(|e| e.key == name) {
+                    entry.value = value;
+                    entry.source = path.display().to_string();
+                }
+            }
+        }
+
+        for (key, entry) in CONFIG_KEYS.iter().zip(entries.iter_mut()) {
+            if let Some(value) = env::var(key.env).ok().filter(|v| !v.is_empty()) {
+                entry.value = value;
+                entry.source = format!("env {}", key.env);
+            }
+            if let Some((_, Some(value))) = flags.iter().find(|(name, _)| *name == key.name) {
+                entry.value = value.clone();
+                entry.source = key.flag.to_string();
+            }
+            if !key.values.is_empty() && !key.values.contains(&entry.value.as_str()) {
+                return Err(format!(
+                    "{} 的值 {} 不合法(来源: {}), 可选值: {}",
+                    key.name,
+                    entry.value,
+                    entry.source,
+                    key.values.join(", ")
+                ));
+            }
+        }
+
+        Ok(GlobalConfig { entries })
+    }
+
+    /// 获取配置项的值, 未设置时返回空字符串
+    pub fn get(&self, key: &str) -> &str {
+        self.entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.value.as_str())
+            .unwrap_or_default()
+    }
+
+    /// 安装根目录
+    pub fn root(&self) -> PathBuf {
+        PathBuf::from(self.get("root"))
+    }
+
+    /// 列表类命令的输出格式
+    pub fn output(&self) -> &str {
+        self.get("output")
+    }
+
+    /// 所有配置项及其来源
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+}
+
+/// 获取用户级配置文件的路径, 优先使用 XDG_CONFIG_HOME, 否则为 ~/.config
+fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join(USER_CONFIG_PATH))
+}
+
+/// 读取配置文件中的配置项, 文件不存在时返回空列表, 不认识的键会被忽略
+/// # Arguments
+/// * `path` - 配置文件路径
+/// # Return
+/// * Ok(Vec<(String, String)>) - (配置项名称, 值)
+/// * Err(String) - 配置文件读取或解析失败, 或配置项不是字符串
+fn read_config_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    let table: toml::Table =
+        toml::from_str(&contents).map_err(|e| format!("解析 {} 失败: {}", path.display(), e))?;
+
+    // 未知的配置项多半是拼写错误, 提示后忽略. 警告写入 stderr, 避免混入供脚本解析的输出
+    for name in table.keys() {
+        if !CONFIG_KEYS.iter().any(|key| key.name == name) {
+            let known: Vec<&str> = CONFIG_KEYS.iter().map(|key| key.name).collect();
+            eprintln!(
+                "{}",
+                format!(
+                    "{} 中的配置项 {} 未知, 已忽略, 支持的配置项: {}",
+                    path.display(),
+                    name,
+                    known.join(", ")
+                )
+                .yellow()
+            );
+        }
+    }
+
+    let mut values = Vec::new();
+    for key in &CONFIG_KEYS {
+        match table.get(key.name) {
+            None => {}
+            Some(toml::Value::String(value)) => values.push((key.name.to_string(), value.clone())),
+            Some(_) => {
+                return Err(format!(
+                    "解析 {} 失败: {} 必须是字符串",
+                    path.display(),
+                    key.name
+                ))
+            }
+        }
+    }
+    Ok(values)
+}
@@ -0,0 +1,340 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-14
+ * File: deb
+ */
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::Builder;
+
+use crate::common::common::{get_system_info, module_constants, VersionMetaData};
+use crate::common::depends::{self, Dependency};
+use crate::common::package::{
+    append_directory, append_file, append_symlink, collect_package_files, io_error, now,
+    shell_quote, PackageFileKind, MAINTAINER_SCRIPTS,
+};
+
+/// 根据模块名称生成 Debian 软件包名称, ex: perception_lidar -> waytous-perception-lidar
+/// 模块的每个版本都以 Provides 提供该名称, 模块间的依赖与冲突都使用该名称
+pub fn debian_package_name(module_name: &str) -> String {
+    format!("waytous-{}", module_name.to_lowercase().replace('_', "-"))
+}
+
+/// 根据模块名称与版本生成带版本号的 Debian 软件包名称, ex: perception_lidar 1.2.0 -> waytous-perception-lidar-1.2.0
+/// 每个版本是一个独立的软件包, 因此同一模块的多个版本可以同时安装
+pub fn debian_versioned_package_name(module_name: &str, module_version: &str) -> String {
+    format!(
+        "{}-{}",
+        debian_package_name(module_name),
+        module_version.to_lowercase()
+    )
+}
+
+/// 根据模块平台生成 Debian 架构名称, 未设置平台时使用当前系统架构
+pub fn debian_architecture(platform: &str) -> String {
+    match platform {
+        "" => match get_system_info().architecture.as_str() {
+            "x86_64" => "amd64".to_string(),
+            "aarch64" => "arm64".to_string(),
+            architecture => architecture.to_string(),
+        },
+        platform => platform.to_string(),
+    }
+}
+
+/// 将模块打包为 Debian 软件包, 安装位置为 /opt/waytous/modules/<name>/<version>.
+/// 软件包名称带有版本号, 多个版本可以同时安装. current 符号链接不属于软件包,
+/// 由 postinst 指向新安装的版本, 之后由 module activate 切换
+/// # Arguments
+/// * `metadata` - 模块元数据, 会以 version.toml 写入安装目录
+/// * `install_dir` - 模块的安装目录树
+/// * `scripts_dir` - 维护脚本所在目录, 目录中的 preinst/postinst/prerm/postrm 会被打包
+/// * `output_dir` - 软件包的输出目录
+/// # Return
+/// * Ok(PathBuf) - 生成的软件包路径
+/// * Err(String) - 打包失败的原因
+pub fn write_deb(
+    metadata: &VersionMetaData,
+    install_dir: &Path,
+    scripts_dir: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf, String> {
+    let files = collect_package_files(metadata, install_dir)?;
+    let module_dir = format!(
+        "{}/{}/{}",
+        module_constants::DEFAULT_ROOT.trim_start_matches('/'),
+        module_constants::MODULES_DIR_NAME,
+        metadata.name
+    );
+    let target_dir = format!("{}/{}", module_dir, metadata.version);
+
+    // 生成 data.tar.gz, 同时计算 md5sums 与安装大小
+    let mut md5sums = String::new();
+    let mut installed_size: u64 = 0;
+    let mut conffiles = String::new();
+    let mut data = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut parent = String::from(".");
+    for component in target_dir.split('/') {
+        parent = format!("{}/{}", parent, component);
+        append_directory(&mut data, &parent, 0o755, now()).map_err(io_error)?;
+    }
+    for file in &files {
+        let path = format!("./{}/{}", target_dir, file.relative_path);
+        match &file.kind {
+            PackageFileKind::Directory => {
+                append_directory(&mut data, &path, file.mode, file.mtime).map_err(io_error)?
+            }
+            PackageFileKind::File(contents) => {
+                append_file(&mut data, &path, file.mode, file.mtime, contents).map_err(io_error)?;
+                md5sums.push_str(&format!(
+                    "{:x}  {}/{}\n",
+                    md5::compute(contents),
+                    target_dir,
+                    file.relative_path
+                ));
+                installed_size += contents.len() as u64;
+                if file.relative_path.starts_with("config/") {
+                    conffiles.push_str(&format!("/{}/{}\n", target_dir, file.relative_path));
+                }
+            }
+            PackageFileKind::Symlink(link) => {
+                append_symlink(&mut data, &path, link, file.mtime).map_err(io_error)?
+            }
+        }
+    }
+    let data = data
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(io_error)?;
+
+    // 生成 control.tar.gz
+    let control = control_file(metadata, installed_size)?;
+    let mut control_tar = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    append_file(
+        &mut control_tar,
+        "./control",
+        0o644,
+        now(),
+        control.as_bytes(),
+    )
+    .map_err(io_error)?;
+    append_file(
+        &mut control_tar,
+        "./md5sums",
+        0o644,
+        now(),
+        md5sums.as_bytes(),
+    )
+    .map_err(io_error)?;
+    if !conffiles.is_empty() {
+        append_file(
+            &mut control_tar,
+            "./conffiles",
+            0o644,
+            now(),
+            conffiles.as_bytes(),
+        )
+        .map_err(io_error)?;
+    }
+    for script in MAINTAINER_SCRIPTS {
+        let script_path = scripts_dir.join(script);
+        let user_script = if script_path.is_file() {
+            Some(
+                fs::read(&script_path)
+                    .map_err(|e| format!("读取 {} 失败: {}", script_path.display(), e))?,
+            )
+        } else {
+            None
+        };
+        if let Some(contents) = maintainer_script(script, &module_dir, metadata, user_script)? {
+            append_file(
+                &mut control_tar,
+                &format!("./{}", script),
+                0o755,
+                now(),
+                &contents,
+            )
+            .map_err(io_error)?;
+        }
+    }
+    let control_tar = control_tar
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(io_error)?;
+
+    // 按 ar 格式组装最终的 deb 文件
+    let deb_path = output_dir.join(format!(
+        "{}_{}_{}.deb",
+        debian_versioned_package_name(&metadata.name, &metadata.version),
+        depends::debian_version(&metadata.version),
+        debian_architecture(&metadata.platform)
+    ));
+    let mut deb = fs::File::create(&deb_path)
+        .map_err(|e| format!("创建 {} 失败: {}", deb_path.display(), e))?;
+    deb.write_all(b"!<arch>\n").map_err(io_error)?;
+    write_ar_member(&mut deb, "debian-binary", b"2.0\n").map_err(io_error)?;
+    write_ar_member(&mut deb, "control.tar.gz", &control_tar).map_err(io_error)?;
+    write_ar_member(&mut deb, "data.tar.gz", &data).map_err(io_error)?;
+
+    Ok(deb_path)
+}
+
+/// 生成 Debian control 文件内容
+fn control_file(metadata: &VersionMetaData, installed_size: u64) -> Result<String, String> {
+    let mut control = format!(
+        "Package: {}\nVersion: {}\nArchitecture: {}\nMaintainer: {}\nInstalled-Size: {}\nSection: misc\nPriority: optional\nProvides: {} (= {})\n",
+        debian_versioned_package_name(&metadata.name, &metadata.version),
+        depends::debian_version(&metadata.version),
+        debian_architecture(&metadata.platform),
+        // 优先使用第一个维护者, 其次为作者
+        metadata
+            .maintainers
+            .first()
+            .filter(|m| !m.is_empty())
+            .map(String::as_str)
+            .unwrap_or(if metadata.author.is_empty() { "unknown" } else { &metadata.author }),
+        installed_size.div_ceil(1024),
+        debian_package_name(&metadata.name),
+        depends::debian_version(&metadata.version),
+    );
+
+    // 模块依赖转换为对应的 Debian 软件包, 系统依赖本身就是 Debian 格式, 原样写入
+    let mut debian_depends = Vec::new();
+    for depend in depends::parse_dependencies(&metadata.depends)? {
+        debian_depends.extend(depend.to_debian(&debian_package_name(&depend.name)));
+    }
+    depends::check_system_dependencies(&metadata.system_depends)?;
+    debian_depends.extend(
+        metadata
+            .system_depends
+            .iter()
+            .map(|spec| spec.trim().to_string()),
+    );
+    if !debian_depends.is_empty() {
+        control.push_str(&format!("Depends: {}\n", debian_depends.join(", ")));
+    }
+
+    let debian_conflicts: Vec<String> = depends::parse_dependencies(&metadata.conflicts)?
+        .iter()
+        .flat_map(|conflict: &Dependency| conflict.to_debian(&debian_package_name(&conflict.name)))
+        .collect();
+    if !debian_conflicts.is_empty() {
+        control.push_str(&format!("Conflicts: {}\n", debian_conflicts.join(", ")));
+    }
+
+    if !metadata.homepage.is_empty() {
+        control.push_str(&format!("Homepage: {}\n", metadata.homepage));
+    }
+
+    // 描述信息的第一行为简要描述, 其余行为详细描述
+    let mut description = metadata.description.lines();
+    control.push_str(&format!(
+        "Description: {}\n",
+        description.next().unwrap_or(&metadata.name)
+    ));
+    for line in description {
+        if line.trim().is_empty() {
+            control.push_str(" .\n");
+        } else {
+            control.push_str(&format!(" {}\n", line));
+        }
+    }
+
+    Ok(control)
+}
+
+/// 生成维护脚本, postinst 与 postrm 负责维护 current 符号链接, 模块自带的脚本嵌入其中执行
+/// # Arguments
+/// * `script` - 维护脚本名称
+/// * `module_dir` - 模块目录, 不带开头的 /
+/// * `metadata` - 模块元数据
+/// * `user_script` - 模块自带的同名维护脚本
+/// # Return
+/// * Ok(Some(Vec<u8>)) - 维护脚本内容
+/// * Ok(None) - 不需要该维护脚本
+/// * Err(String) - 模块自带的脚本无法嵌入
+fn maintainer_script(
+    script: &str,
+    module_dir: &str,
+    metadata: &VersionMetaData,
+    user_script: Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, String> {
+    let link_script = match script {
+        "postinst" => POSTINST_LINK,
+        "postrm" => POSTRM_LINK,
+        _ => return Ok(user_script),
+    };
+
+    let mut contents = format!(
+        "#!/bin/sh\nset -e\nMODULE_DIR={}\nMODULE_VERSION={}\n{}",
+        shell_quote(&format!("/{}", module_dir)),
+        shell_quote(&metadata.version),
+        link_script
+    )
+    .into_bytes();
+    if let Some(user_script) = user_script {
+        // 模块自带的脚本可能使用任意解释器, 写入临时文件后按原样执行
+        let delimiter = "WAYTOUS_SCRIPT_EOF";
+        if String::from_utf8_lossy(&user_script)
+            .lines()
+            .any(|line| line == delimiter)
+        {
+            return Err(format!("{} 中不能包含单独一行的 {}", script, delimiter));
+        }
+        contents.extend_from_slice(
+            format!(
+                "SCRIPT=$(mktemp)\ntrap 'rm -f \"$SCRIPT\"' EXIT\ncat > \"$SCRIPT\" <<'{}'\n",
+                delimiter
+            )
+            .as_bytes(),
+        );
+        contents.extend_from_slice(&user_script);
+        if !user_script.ends_with(b"\n") {
+            contents.push(b'\n');
+        }
+        contents.extend_from_slice(
+            format!("{}\nchmod 755 \"$SCRIPT\"\n\"$SCRIPT\" \"$@\"\n", delimiter).as_bytes(),
+        );
+    }
+    Ok(Some(contents))
+}
+
+/// 安装后将 current 指向新安装的版本, 与 run 安装包的行为一致
+const POSTINST_LINK: &str = r#"if [ "$1" = "configure" ]; then
+    ln -sfn "$MODULE_VERSION" "$MODULE_DIR/current"
+fi
+"#;
+
+/// 卸载后 current 指向该版本时一并删除, 其他版本保留
+const POSTRM_LINK: &str = r#"if [ "$1" = "remove" ] || [ "$1" = "purge" ]; then
+    if [ "$(readlink "$MODULE_DIR/current" 2>/dev/null)" = "$MODULE_VERSION" ]; then
+        rm -f "$MODULE_DIR/current"
+    fi
+    rmdir "$MODULE_DIR" 2>/dev/null || true
+fi
+"#;
+
+/// 写入一个 ar 归档成员
+fn write_ar_member<W: Write>(writer: &mut W, name: &str, data: &[u8]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`",
+        name,
+        now(),
+        0,
+        0,
+        "100644",
+        data.len()
+    )?;
+    writer.write_all(data)?;
+    // ar 成员需要按 2 字节对齐
+    if data.len() % 2 == 1 {
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
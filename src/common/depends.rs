@@ -0,0 +1,423 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-13
+ * File: depends
+ */
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use semver::{Comparator, Op, Version, VersionReq};
+
+use crate::common::common::VersionMetaData;
+
+/// 模块依赖声明
+/// 格式: "name" 或 "name (>= 1.0.0)" 或 "name (>= 1.0.0, < 2.0.0)"
+pub struct Dependency {
+    /// 模块名称
+    pub name: String,
+
+    /// 版本约束, 为空时表示任意版本
+    pub requirement: Option<VersionReq>,
+}
+
+impl Dependency {
+    /// 解析依赖声明
+    /// # Arguments
+    /// * `spec` - 依赖声明
+    /// # Return
+    /// * Ok(Dependency) - 解析后的依赖
+    /// * Err(String) - 依赖声明格式错误
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let (name, requirement) = match spec.find('(') {
+            Some(start) => {
+                if !spec.ends_with(')') {
+                    return Err(format!("依赖声明 {} 格式错误, 缺少 ')'", spec));
+                }
+                let requirement = spec[start + 1..spec.len() - 1].trim();
+                let requirement = VersionReq::parse(requirement).map_err(|e| {
+                    format!("依赖声明 {} 的版本约束 {} 无效: {}", spec, requirement, e)
+                })?;
+                (spec[..start].trim(), Some(requirement))
+            }
+            None => (spec, None),
+        };
+
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("依赖声明 {} 的模块名称无效", spec));
+        }
+
+        Ok(Dependency {
+            name: name.to_string(),
+            requirement,
+        })
+    }
+
+    /// 检查指定版本是否满足版本约束
+    /// # Arguments
+    /// * `version` - 模块版本号
+    /// # Return
+    /// * true - 满足约束
+    /// * false - 不满足约束或版本号无法解析
+    pub fn matches(&self, version: &str) -> bool {
+        match &self.requirement {
+            Some(requirement) => Version::parse(version)
+                .map(|v| requirement.matches(&v))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// 转换为 Debian control 文件中的依赖格式
+    /// # Arguments
+    /// * `package_name` - 依赖对应的 Debian 软件包名称
+    /// # Return
+    /// * 依赖列表, 一个版本约束可能会被拆分成多条, ex: "foo (>= 1.2.0), foo (<< 2.0.0)"
+    pub fn to_debian(&self, package_name: &str) -> Vec<String> {
+        let constraints: Vec<(&str, String)> = self
+            .requirement
+            .iter()
+            .flat_map(|requirement| requirement.comparators.iter())
+            .flat_map(comparator_to_debian)
+            .collect();
+
+        if constraints.is_empty() {
+            vec![package_name.to_string()]
+        } else {
+            constraints
+                .into_iter()
+                .map(|(op, version)| format!("{} ({} {})", package_name, op, version))
+                .collect()
+        }
+    }
+}
+
+/// 将 SemVer 版本号转换为 Debian 版本号, 预发布版本使用 '~' 连接以保证排序正确
+/// ex: 1.2.0-rc.1 -> 1.2.0~rc.1
+pub fn debian_version(version: &str) -> String {
+    match Version::parse(version) {
+        Ok(version) => {
+            let mut debian_version =
+                format!("{}.{}.{}", version.major, version.minor, version.patch);
+            if !version.pre.is_empty() {
+                debian_version.push('~');
+                debian_version.push_str(version.pre.as_str());
+            }
+            if !version.build.is_empty() {
+                debian_version.push('+');
+                debian_version.push_str(version.build.as_str());
+            }
+            debian_version
+        }
+        Err(_) => version.replace('-', "~"),
+    }
+}
+
+/// 将单个 SemVer 版本约束转换为 Debian 版本约束
+fn comparator_to_debian(comparator: &Comparator) -> Vec<(&'static str, String)> {
+    let major = comparator.major;
+    let minor = comparator.minor;
+    let patch = comparator.patch;
+    let lower = {
+        let mut version = format!("{}.{}.{}", major, minor.unwrap_or(0), patch.unwrap_or(0));
+        if !comparator.pre.is_empty() {
+            version.push('~');
+            version.push_str(comparator.pre.as_str());
+        }
+        version
+    };
+    // 部分版本号(1.2 或 1)所覆盖范围的上界
+    let partial_upper = match minor {
+        Some(minor) => format!("{}.{}.0", major, minor + 1),
+        None => format!("{}.0.0", major + 1),
+    };
+
+    match comparator.op {
+        Op::Exact | Op::Wildcard if patch.is_some() => vec![("=", lower)],
+        Op::Exact | Op::Wildcard => vec![(">=", lower), ("<<", partial_upper)],
+        Op::Greater if patch.is_some() => vec![(">>", lower)],
+        Op::Greater => vec![(">=", partial_upper)],
+        Op::GreaterEq => vec![(">=", lower)],
+        Op::Less => vec![("<<", lower)],
+        Op::LessEq if patch.is_some() => vec![("<=", lower)],
+        Op::LessEq => vec![("<<", partial_upper)],
+        Op::Tilde => vec![(">=", lower), ("<<", partial_upper)],
+        Op::Caret => {
+            let upper = match (minor, patch) {
+                _ if major > 0 => format!("{}.0.0", major + 1),
+                (Some(minor), _) if minor > 0 => format!("0.{}.0", minor + 1),
+                (Some(_), Some(patch)) => format!("0.0.{}", patch + 1),
+                (Some(_), None) => "0.1.0".to_string(),
+                (None, _) => "1.0.0".to_string(),
+            };
+            vec![(">=", lower), ("<<", upper)]
+        }
+        _ => vec![(">=", lower)],
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.requirement {
+            Some(requirement) => write!(f, "{} ({})", self.name, requirement),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// 检查系统依赖声明, 系统依赖使用 Debian control 文件中 Depends 字段的格式并原样写入软件包,
+/// 版本号为 Debian 版本号, 可以用 '|' 连接多个可选的软件包
+/// ex: "libfoo (>= 2:1.0-1ubuntu2)", "libbar:amd64 | libbaz"
+/// # Arguments
+/// * `spec` - 系统依赖声明
+/// # Return
+/// * Ok(()) - 格式正确
+/// * Err(String) - 格式错误的原因
+pub fn check_system_dependency(spec: &str) -> Result<(), String> {
+    for alternative in spec.split('|') {
+        let alternative = alternative.trim();
+        let (package, relation) = match alternative.find('(') {
+            Some(start) => {
+                let Some(relation) = alternative[start + 1..].strip_suffix(')') else {
+                    return Err(format!("系统依赖 {} 格式错误, 缺少 ')'", spec));
+                };
+                (alternative[..start].trim(), Some(relation.trim()))
+            }
+            None => (alternative, None),
+        };
+
+        let (name, arch) = package.split_once(':').unwrap_or((package, ""));
+        let valid_name = name.len() >= 2
+            && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+        let valid_arch = !package.contains(':')
+            || (!arch.is_empty()
+                && arch
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'));
+        if !valid_name || !valid_arch {
+            return Err(format!("系统依赖 {} 的软件包名称 {} 无效", spec, package));
+        }
+
+        if let Some(relation) = relation {
+            let op = ["<<", "<=", ">=", ">>", "="]
+                .into_iter()
+                .find(|op| relation.starts_with(op))
+                .ok_or(format!(
+                    "系统依赖 {} 的版本约束 {} 无效, 可用的关系: <<, <=, =, >=, >>",
+                    spec, relation
+                ))?;
+            let version = relation[op.len()..].trim();
+            let valid_version = version.starts_with(|c: char| c.is_ascii_digit())
+                && version
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".+~:-".contains(c));
+            if !valid_version {
+                return Err(format!("系统依赖 {} 的版本号 {} 无效", spec, version));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 检查系统依赖声明列表, 遇到第一个格式错误时返回
+pub fn check_system_dependencies(specs: &[String]) -> Result<(), String> {
+    specs
+        .iter()
+        .try_for_each(|spec| check_system_dependency(spec))
+}
+
+/// 解析依赖声明列表, 遇到第一个格式错误时返回
+pub fn parse_dependencies(specs: &[String]) -> Result<Vec<Dependency>, String> {
+    specs.iter().map(|spec| Dependency::parse(spec)).collect()
+}
+
+/// 检查模块在已安装模块中未满足的依赖以及冲突
+/// # Arguments
+/// * `metadata` - 模块元数据
+/// * `installed` - 已安装的模块, key 为模块名称, value 为版本号
+/// # Return
+/// * 问题描述列表, 为空时表示依赖全部满足
+pub fn find_unmet_dependencies(
+    metadata: &VersionMetaData,
+    installed: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for spec in &metadata.depends {
+        match Dependency::parse(spec) {
+            Ok(depend) => match installed.get(&depend.name) {
+                Some(version) if depend.matches(version) => {}
+                Some(version) => {
+                    problems.push(format!("依赖 {} 不满足, 已安装 {}", depend, version))
+                }
+                None => problems.push(format!("缺少依赖 {}", depend)),
+            },
+            Err(err) => problems.push(err),
+        }
+    }
+
+    for spec in &metadata.conflicts {
+        match Dependency::parse(spec) {
+            Ok(conflict) => {
+                if let Some(version) = installed.get(&conflict.name) {
+                    if conflict.matches(version) {
+                        problems.push(format!("与已安装的 {} {} 冲突", conflict.name, version));
+                    }
+                }
+            }
+            Err(err) => problems.push(err),
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed(modules: &[(&str, &str)]) -> BTreeMap<String, String> {
+        modules
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect()
+    }
+
+    fn debian(spec: &str) -> Vec<String> {
+        Dependency::parse(spec).unwrap().to_debian("waytous-map")
+    }
+
+    #[test]
+    fn dependency_without_requirement_matches_any_version() {
+        let depend = Dependency::parse(" map ").unwrap();
+        assert_eq!(depend.name, "map");
+        assert!(depend.requirement.is_none());
+        assert!(depend.matches("0.0.1"));
+        assert_eq!(depend.to_string(), "map");
+    }
+
+    #[test]
+    fn dependency_requirement_is_checked() {
+        let depend = Dependency::parse("map (>= 1.2.0, < 2.0.0)").unwrap();
+        assert_eq!(depend.name, "map");
+        assert!(depend.matches("1.2.0"));
+        assert!(depend.matches("1.9.9"));
+        assert!(!depend.matches("2.0.0"));
+        assert!(!depend.matches("not-a-version"));
+        assert_eq!(depend.to_string(), "map (>=1.2.0, <2.0.0)");
+    }
+
+    #[test]
+    fn malformed_dependencies_are_rejected() {
+        assert!(Dependency::parse("map (>= 1.2.0").is_err());
+        assert!(Dependency::parse("map (latest)").is_err());
+        assert!(Dependency::parse("(>= 1.0.0)").is_err());
+        assert!(Dependency::parse("hd map").is_err());
+    }
+
+    #[test]
+    fn unmet_dependencies_and_conflicts_are_reported() {
+        let metadata = VersionMetaData {
+            depends: vec![
+                "base".to_string(),
+                "map (>= 2.0.0)".to_string(),
+                "planning".to_string(),
+            ],
+            conflicts: vec!["legacy_map".to_string(), "control (< 1.0.0)".to_string()],
+            ..Default::default()
+        };
+        let installed = installed(&[
+            ("base", "0.1.0"),
+            ("map", "1.5.0"),
+            ("legacy_map", "0.3.0"),
+            ("control", "1.2.0"),
+        ]);
+        assert_eq!(
+            find_unmet_dependencies(&metadata, &installed),
+            vec![
+                "依赖 map (>=2.0.0) 不满足, 已安装 1.5.0",
+                "缺少依赖 planning",
+                "与已安装的 legacy_map 0.3.0 冲突",
+            ]
+        );
+    }
+
+    #[test]
+    fn unconstrained_dependency_is_the_package_name() {
+        assert_eq!(debian("map"), vec!["waytous-map"]);
+    }
+
+    #[test]
+    fn comparison_operators_map_to_debian_relations() {
+        assert_eq!(debian("map (=1.2.3)"), vec!["waytous-map (= 1.2.3)"]);
+        assert_eq!(debian("map (>1.2.3)"), vec!["waytous-map (>> 1.2.3)"]);
+        assert_eq!(debian("map (>=1.2.3)"), vec!["waytous-map (>= 1.2.3)"]);
+        assert_eq!(debian("map (<1.2.3)"), vec!["waytous-map (<< 1.2.3)"]);
+        assert_eq!(debian("map (<=1.2.3)"), vec!["waytous-map (<= 1.2.3)"]);
+    }
+
+    #[test]
+    fn partial_versions_cover_the_whole_range() {
+        assert_eq!(
+            debian("map (=1.2)"),
+            vec!["waytous-map (>= 1.2.0)", "waytous-map (<< 1.3.0)"]
+        );
+        assert_eq!(debian("map (>1.2)"), vec!["waytous-map (>= 1.3.0)"]);
+        assert_eq!(debian("map (<=1)"), vec!["waytous-map (<< 2.0.0)"]);
+        assert_eq!(
+            debian("map (1.*)"),
+            vec!["waytous-map (>= 1.0.0)", "waytous-map (<< 2.0.0)"]
+        );
+    }
+
+    #[test]
+    fn tilde_and_caret_have_upper_bounds() {
+        assert_eq!(
+            debian("map (~1.2.3)"),
+            vec!["waytous-map (>= 1.2.3)", "waytous-map (<< 1.3.0)"]
+        );
+        assert_eq!(
+            debian("map (^1.2.3)"),
+            vec!["waytous-map (>= 1.2.3)", "waytous-map (<< 2.0.0)"]
+        );
+        assert_eq!(
+            debian("map (^0.2.3)"),
+            vec!["waytous-map (>= 0.2.3)", "waytous-map (<< 0.3.0)"]
+        );
+        assert_eq!(
+            debian("map (^0.0.3)"),
+            vec!["waytous-map (>= 0.0.3)", "waytous-map (<< 0.0.4)"]
+        );
+    }
+
+    #[test]
+    fn prerelease_uses_tilde() {
+        assert_eq!(
+            debian("map (>=1.2.0-rc.1)"),
+            vec!["waytous-map (>= 1.2.0~rc.1)"]
+        );
+        assert_eq!(debian_version("1.2.0-rc.1"), "1.2.0~rc.1");
+        assert_eq!(debian_version("1.2.0+build.5"), "1.2.0+build.5");
+    }
+
+    #[test]
+    fn multiple_comparators_become_separate_relations() {
+        assert_eq!(
+            debian("map (>=1.0.0, <2.0.0)"),
+            vec!["waytous-map (>= 1.0.0)", "waytous-map (<< 2.0.0)"]
+        );
+    }
+
+    #[test]
+    fn system_dependencies_are_debian_relations() {
+        assert!(check_system_dependency("libfoo (>= 2:1.0-1ubuntu2)").is_ok());
+        assert!(check_system_dependency("libbar:amd64 | libbaz").is_ok());
+        assert!(check_system_dependency("libfoo (> 1.0)").is_err());
+        assert!(check_system_dependency("LibFoo").is_err());
+        assert!(check_system_dependency("libfoo (>= 1.0").is_err());
+    }
+}
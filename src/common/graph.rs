@@ -0,0 +1,168 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-12
+ * File: graph
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// 依赖关系图, key 为节点名称, value 为该节点所依赖的节点列表
+pub type DependencyGraph = BTreeMap<String, Vec<String>>;
+
+/// 对依赖关系图进行拓扑排序, 被依赖的节点排在前面
+/// # Arguments
+/// * `graph` - 依赖关系图, 不在图中的依赖节点会被忽略
+/// # Return
+/// * Ok(Vec<String>) - 排序后的节点列表
+/// * Err(String) - 图中存在循环依赖, 错误信息中包含循环路径
+pub fn topological_sort(graph: &DependencyGraph) -> Result<Vec<String>, String> {
+    // 统计每个节点尚未满足的依赖数量
+    let mut in_degree: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut dependents: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, depends) in graph {
+        let depends: BTreeSet<&str> = depends
+            .iter()
+            .map(|d| d.as_str())
+            .filter(|d| graph.contains_key(*d))
+            .collect();
+        in_degree.insert(name, depends.len());
+        for depend in depends {
+            dependents.entry(depend).or_default().push(name);
+        }
+    }
+
+    let mut ready: BTreeSet<&str> = in_degree
+        .iter()
+        .filter(|(_, degree)| **degree == 0)
+        .map(|(name, _)| *name)
+        .collect();
+    let mut order = Vec::with_capacity(graph.len());
+
+    while let Some(name) = ready.pop_first() {
+        order.push(name.to_string());
+        for dependent in dependents.get(name).into_iter().flatten() {
+            let degree = in_degree.get_mut(dependent).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.insert(dependent);
+            }
+        }
+    }
+
+    if order.len() == graph.len() {
+        Ok(order)
+    } else {
+        let cycle = find_cycle(graph).unwrap_or_default();
+        Err(format!("检测到循环依赖: {}", cycle.join(" -> ")))
+    }
+}
+
+/// 在依赖关系图中查找一条循环依赖路径, 路径首尾为同一个节点
+pub fn find_cycle(graph: &DependencyGraph) -> Option<Vec<String>> {
+    // 0: 未访问, 1: 访问中, 2: 已完成
+    let mut state: BTreeMap<&str, u8> = BTreeMap::new();
+    let mut stack: Vec<&str> = Vec::new();
+
+    fn visit<'a>(
+        name: &'a str,
+        graph: &'a DependencyGraph,
+        state: &mut BTreeMap<&'a str, u8>,
+        stack: &mut Vec<&'a str>,
+    ) -> Option<Vec<String>> {
+        state.insert(name, 1);
+        stack.push(name);
+        for depend in graph.get(name).into_iter().flatten() {
+            if !graph.contains_key(depend) {
+                continue;
+            }
+            match state.get(depend.as_str()).copied().unwrap_or(0) {
+                0 => {
+                    if let Some(cycle) = visit(depend, graph, state, stack) {
+                        return Some(cycle);
+                    }
+                }
+                1 => {
+                    let start = stack.iter().position(|n| *n == depend).unwrap();
+                    let mut cycle: Vec<String> =
+                        stack[start..].iter().map(|n| n.to_string()).collect();
+                    cycle.push(depend.to_string());
+                    return Some(cycle);
+                }
+                _ => {}
+            }
+        }
+        stack.pop();
+        state.insert(name, 2);
+        None
+    }
+
+    for name in graph.keys() {
+        if state.get(name.as_str()).copied().unwrap_or(0) == 0 {
+            if let Some(cycle) = visit(name, graph, &mut state, &mut stack) {
+                return Some(cycle);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        edges
+            .iter()
+            .map(|(name, depends)| {
+                (
+                    name.to_string(),
+                    depends.iter().map(|d| d.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dependencies_come_first() {
+        let graph = graph(&[("app", &["map", "base"]), ("map", &["base"]), ("base", &[])]);
+        assert_eq!(
+            topological_sort(&graph).unwrap(),
+            vec!["base", "map", "app"]
+        );
+    }
+
+    #[test]
+    fn independent_nodes_are_sorted_by_name() {
+        let graph = graph(&[("c", &[]), ("a", &[]), ("b", &["a"])]);
+        assert_eq!(topological_sort(&graph).unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn depends_outside_the_graph_are_ignored() {
+        let graph = graph(&[("app", &["libc"]), ("map", &["app", "app"])]);
+        assert_eq!(topological_sort(&graph).unwrap(), vec!["app", "map"]);
+    }
+
+    #[test]
+    fn cycle_is_reported_with_its_path() {
+        let graph = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &[])]);
+        assert_eq!(find_cycle(&graph).unwrap(), vec!["a", "b", "c", "a"]);
+        assert_eq!(
+            topological_sort(&graph).unwrap_err(),
+            "检测到循环依赖: a -> b -> c -> a"
+        );
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let graph = graph(&[("a", &["a"])]);
+        assert_eq!(find_cycle(&graph).unwrap(), vec!["a", "a"]);
+        assert!(topological_sort(&graph).is_err());
+    }
+
+    #[test]
+    fn acyclic_graph_has_no_cycle() {
+        let graph = graph(&[("a", &["b", "c"]), ("b", &["c"]), ("c", &[])]);
+        assert!(find_cycle(&graph).is_none());
+    }
+}
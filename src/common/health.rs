@@ -0,0 +1,234 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-26
+ * File: health
+ */
+
+use std::fmt;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::common;
+use crate::common::program::Program;
+
+/// 健康检查的配置, 对应 supervise.toml 中的 [readiness] 与 [liveness]
+/// 就绪检查在程序启动后执行, 成功后程序进入 running 状态, start_timeout 内未成功视为启动失败;
+/// 存活检查在程序 running 后执行, 连续失败 failure_threshold 次后重启程序
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct HealthCheck {
+    /// 检查方式
+    #[serde(flatten)]
+    pub probe: Probe,
+
+    /// 第一次检查前等待的秒数
+    #[serde(default)]
+    pub initial_delay: u64,
+
+    /// 两次检查之间的秒数
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+
+    /// 单次检查的超时秒数, 只对 exec 与 tcp 有效
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+
+    /// 存活检查连续失败多少次后重启程序
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+fn default_timeout() -> u64 {
+    3
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+/// 检查方式
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Probe {
+    /// 执行命令, 退出码为 0 表示健康, 命令的路径规则、运行用户与环境变量与程序的 command 相同
+    Exec {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+
+    /// TCP 端口可以连接表示健康
+    Tcp {
+        #[serde(default = "default_host")]
+        host: String,
+        port: u16,
+    },
+
+    /// 文件在 max_age 秒内被修改过表示健康, 相对路径相对于模块目录
+    File { path: String, max_age: u64 },
+
+    /// 程序在 max_age 秒内通过心跳套接字发送过心跳表示健康, 程序启动时开始计时
+    Heartbeat { max_age: u64 },
+}
+
+impl HealthCheck {
+    /// 检查配置中的取值
+    /// # Return
+    /// * Vec<String> - 不合法的取值说明, 为空表示正确
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.interval == 0 {
+            errors.push("interval 必须大于 0 秒".to_string());
+        }
+        if self.timeout == 0 {
+            errors.push("timeout 必须大于 0 秒".to_string());
+        }
+        if self.failure_threshold == 0 {
+            errors.push("failure_threshold 必须大于 0".to_string());
+        }
+        match &self.probe {
+            Probe::Exec { command, .. } if command.trim().is_empty() => {
+                errors.push("command 不能为空".to_string())
+            }
+            Probe::Tcp { port: 0, .. } => errors.push("port 必须大于 0".to_string()),
+            Probe::File { path, .. } if path.is_empty() => errors.push("path 不能为空".to_string()),
+            Probe::File { max_age: 0, .. } | Probe::Heartbeat { max_age: 0 } => {
+                errors.push("max_age 必须大于 0 秒".to_string())
+            }
+            _ => {}
+        }
+        errors
+    }
+}
+
+/// 程序的健康状态
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    /// 没有配置健康检查或程序未运行
+    #[default]
+    None,
+
+    /// 尚未得到检查结果
+    Pending,
+
+    /// 最近一次检查成功
+    Healthy,
+
+    /// 最近一次检查失败
+    Unhealthy,
+}
+
+impl fmt::Display for HealthState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HealthState::None => "",
+            HealthState::Pending => "pending",
+            HealthState::Healthy => "healthy",
+            HealthState::Unhealthy => "unhealthy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 执行一次 exec、tcp 或 file 检查, 心跳检查由 supervisord 根据收到心跳的时刻判断
+/// # Arguments
+/// * `probe` - 检查方式
+/// * `program` - 被检查的程序, exec 检查以程序的用户与环境变量执行, 相对路径相对于模块目录
+/// * `timeout` - 超时秒数
+/// # Return
+/// * Ok(()) - 健康
+/// * Err(String) - 不健康的原因
+pub fn probe(probe: &Probe, program: &Program, timeout: u64) -> Result<(), String> {
+    let module_dir = &program.module_dir;
+    let timeout = Duration::from_secs(timeout);
+    match probe {
+        Probe::Exec { command, args } => {
+            let path = if command.contains('/') {
+                module_dir.join(command)
+            } else {
+                command.into()
+            };
+            let mut exec = Command::new(&path);
+            exec.args(args)
+                .current_dir(module_dir)
+                .envs(&program.environment)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            if !program.user.is_empty() {
+                let (uid, gid) = common::user_ids(&program.user)
+                    .ok_or(format!("用户 {} 不存在", program.user))?;
+                exec.uid(uid).gid(gid);
+            }
+            let mut child = exec
+                .spawn()
+                .map_err(|e| format!("执行 {} 失败: {}", command, e))?;
+            let deadline = Instant::now() + timeout;
+            loop {
+                match child.try_wait() {
+                    Ok(Some(status)) if status.success() => return Ok(()),
+                    Ok(Some(status)) => return Err(format!("{} 返回 {}", command, status)),
+                    Ok(None) if Instant::now() >= deadline => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(format!("{} 超时", command));
+                    }
+                    Ok(None) => thread::sleep(Duration::from_millis(50)),
+                    Err(err) => return Err(format!("等待 {} 失败: {}", command, err)),
+                }
+            }
+        }
+        Probe::Tcp { host, port } => {
+            let addr = (host.as_str(), *port)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or(format!("无法解析地址 {}:{}", host, port))?;
+            TcpStream::connect_timeout(&addr, timeout)
+                .map(|_| ())
+                .map_err(|e| format!("连接 {}:{} 失败: {}", host, port, e))
+        }
+        Probe::File { path, max_age } => {
+            let path = module_dir.join(path);
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default()
+                .as_secs();
+            if age <= *max_age {
+                Ok(())
+            } else {
+                Err(format!("{} 已有 {} 秒未更新", path.display(), age))
+            }
+        }
+        Probe::Heartbeat { .. } => Ok(()),
+    }
+}
+
+/// 根据最近一次心跳的时刻判断是否健康
+/// # Arguments
+/// * `last` - 最近一次收到心跳的时刻
+/// * `max_age` - 允许的最长间隔秒数
+pub fn check_heartbeat(last: Option<Instant>, max_age: u64) -> Result<(), String> {
+    match last {
+        Some(last) if last.elapsed().as_secs() <= max_age => Ok(()),
+        Some(last) => Err(format!("已有 {} 秒未收到心跳", last.elapsed().as_secs())),
+        None => Err("未收到心跳".to_string()),
+    }
+}
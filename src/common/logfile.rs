@@ -0,0 +1,232 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-26
+ * File: logfile
+ */
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::common;
+
+/// 程序输出的保存方式, 对应 supervise.toml 中的 [log]
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct LogConfig {
+    /// 单个日志文件的最大大小(MB), 超过后轮转
+    #[serde(default = "default_max_size")]
+    pub max_size: u64,
+
+    /// 轮转后保留的历史日志文件数量, 为 0 时轮转直接清空日志
+    #[serde(default = "default_max_files")]
+    pub max_files: u32,
+}
+
+fn default_max_size() -> u64 {
+    10
+}
+
+fn default_max_files() -> u32 {
+    5
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            max_size: default_max_size(),
+            max_files: default_max_files(),
+        }
+    }
+}
+
+/// 日志中的一行
+#[derive(Serialize)]
+pub struct LogLine {
+    /// 写入时间, RFC 3339 格式
+    pub time: String,
+
+    /// 来源, stdout/stderr/supervisord
+    pub stream: String,
+
+    /// 内容
+    pub message: String,
+}
+
+/// 获取程序的日志文件路径, ex: /opt/waytous/log/planning.log
+pub fn log_path(root: &Path, name: &str) -> PathBuf {
+    common::get_log_path(root).join(format!("{}.log", name))
+}
+
+/// 获取程序已存在的日志文件, 从最旧的历史文件到当前文件排列
+pub fn log_files(root: &Path, name: &str) -> Vec<PathBuf> {
+    let path = log_path(root, name);
+    let mut files: Vec<(u32, PathBuf)> = fs::read_dir(common::get_log_path(root))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let index = file_name
+                .strip_prefix(&format!("{}.log.", name))?
+                .parse::<u32>()
+                .ok()?;
+            Some((index, entry.path()))
+        })
+        .collect();
+    files.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+    let mut files: Vec<PathBuf> = files.into_iter().map(|(_, path)| path).collect();
+    if path.exists() {
+        files.push(path);
+    }
+    files
+}
+
+/// 解析日志中的一行, 格式: <time> [<stream>] <message>
+pub fn parse_line(line: &str) -> Option<LogLine> {
+    let (time, rest) = line.split_once(' ')?;
+    let (stream, message) = rest.strip_prefix('[')?.split_once("] ")?;
+    Some(LogLine {
+        time: time.to_string(),
+        stream: stream.to_string(),
+        message: message.to_string(),
+    })
+}
+
+/// 按大小轮转的日志文件, 在每条记录写完后检查大小, 保证一行不会被拆到两个文件中,
+/// 轮转时 <name>.log 重命名为 <name>.log.1, 原有的 <name>.log.N 依次后移, 超出数量的被删除
+struct RotatingFile {
+    /// 当前日志文件路径
+    path: PathBuf,
+
+    /// 当前日志文件
+    file: File,
+
+    /// 当前日志文件的大小
+    size: u64,
+
+    /// 轮转的大小阈值(字节)
+    max_size: u64,
+
+    /// 保留的历史文件数量
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, config: &LogConfig) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size: config.max_size * 1024 * 1024,
+            max_files: config.max_files,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let history = |index: u32| PathBuf::from(format!("{}.{}", self.path.display(), index));
+        let _ = fs::remove_file(history(self.max_files));
+        for index in (1..self.max_files).rev() {
+            let _ = fs::rename(history(index), history(index + 1));
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, history(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.file.write(buf)?;
+        self.size += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.size >= self.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+/// 创建写入程序日志文件的 logger, 每行前加上写入时间与来源, 来源通过 record 的 target 指定
+/// # Arguments
+/// * `path` - 日志文件路径
+/// * `config` - 轮转配置
+/// # Return
+/// * Ok(Arc<dyn log::Log>) - logger
+/// * Err(String) - 日志文件无法打开
+pub fn logger(path: &Path, config: &LogConfig) -> Result<Arc<dyn log::Log>, String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+    }
+    let file = RotatingFile::open(path, config)
+        .map_err(|e| format!("打开 {} 失败: {}", path.display(), e))?;
+    let (_, logger) = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{} [{}] {}",
+                chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+                record.target(),
+                message
+            ))
+        })
+        .chain(Box::new(file) as Box<dyn Write + Send>)
+        .into_log();
+    Ok(Arc::from(logger))
+}
+
+/// 向日志写入一行
+/// # Arguments
+/// * `logger` - logger
+/// * `stream` - 来源, stdout/stderr/supervisord
+/// * `message` - 内容
+pub fn write_line(logger: &dyn log::Log, stream: &str, message: &str) {
+    logger.log(
+        &log::Record::builder()
+            .args(format_args!("{}", message))
+            .level(log::Level::Info)
+            .target(stream)
+            .build(),
+    );
+}
+
+/// 在独立线程中逐行读取程序的输出并写入日志, 输出关闭后线程退出
+/// # Arguments
+/// * `reader` - 子进程的 stdout 或 stderr
+/// * `stream` - 来源, stdout/stderr
+/// * `logger` - logger
+pub fn capture(
+    reader: impl Read + Send + 'static,
+    stream: &'static str,
+    logger: Arc<dyn log::Log>,
+) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        while let Ok(size) = reader.read_until(b'\n', &mut line) {
+            if size == 0 {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            write_line(logger.as_ref(), stream, text.trim_end_matches(['\r', '\n']));
+            line.clear();
+        }
+    });
+}
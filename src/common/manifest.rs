@@ -0,0 +1,349 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-19
+ * File: manifest
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::common::common::{module_constants, VersionMetaData};
+use crate::common::package::{sha256_hex, PackageFile, PackageFileKind};
+
+/// 模块文件的详细信息
+#[derive(Serialize)]
+pub struct FileDetail {
+    /// 相对于模块安装目录的路径
+    pub path: String,
+
+    /// 文件类型
+    pub kind: FileKind,
+
+    /// 文件大小(字节)
+    pub size: u64,
+
+    /// 文件权限
+    pub mode: u32,
+
+    /// 所属用户 ID
+    pub uid: u32,
+
+    /// 所属用户组 ID
+    pub gid: u32,
+
+    /// 文件修改时间戳(秒)
+    pub mtime: i64,
+
+    /// 文件内容的 SHA-256 值, 仅普通文件有效
+    pub sha256: String,
+
+    /// 符号链接指向的路径, 仅符号链接有效
+    pub link: String,
+}
+
+/// 模块文件的类型
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    Directory,
+    File,
+    Symlink,
+}
+
+/// 模块安装时记录的文件清单
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    /// 模块名称
+    pub name: String,
+
+    /// 模块版本号
+    pub version: String,
+
+    /// 模块包含的文件, 不包含目录
+    #[serde(default)]
+    pub files: Vec<ManifestEntry>,
+}
+
+/// 文件清单中的一个文件
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ManifestEntry {
+    /// 相对于模块安装目录的路径
+    pub path: String,
+
+    /// 文件类型
+    pub kind: FileKind,
+
+    /// 文件大小(字节)
+    pub size: u64,
+
+    /// 文件权限
+    pub mode: u32,
+
+    /// 文件内容的 SHA-256 值, 仅普通文件有效
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sha256: String,
+
+    /// 符号链接指向的路径, 仅符号链接有效
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub link: String,
+}
+
+/// 实际文件与文件清单的差异类型
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    /// 文件内容、类型或权限被修改
+    Modified,
+    /// 文件清单中的文件不存在
+    Missing,
+    /// 文件不在文件清单中
+    Extra,
+}
+
+impl DiffKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffKind::Modified => "modified",
+            DiffKind::Missing => "missing",
+            DiffKind::Extra => "extra",
+        }
+    }
+}
+
+/// 实际文件与文件清单的差异
+#[derive(Serialize)]
+pub struct ManifestDiff {
+    /// 相对于模块安装目录的路径
+    pub path: String,
+
+    /// 差异类型
+    pub kind: DiffKind,
+
+    /// 差异描述
+    pub detail: String,
+}
+
+impl Manifest {
+    /// 序列化为 TOML 格式
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| format!("文件清单序列化失败: {}", e))
+    }
+}
+
+/// 获取模块文件清单的路径
+pub fn manifest_path(module_dir: &Path) -> std::path::PathBuf {
+    module_dir.join(module_constants::MODULE_MANIFEST_NAME)
+}
+
+/// 扫描模块安装目录下的所有文件, 文件清单本身不包含在内
+/// # Arguments
+/// * `module_dir` - 模块安装目录
+/// # Return
+/// * Ok(Vec<FileDetail>) - 按路径排序的文件列表
+/// * Err(String) - 读取失败的原因
+pub fn scan_module_files(module_dir: &Path) -> Result<Vec<FileDetail>, String> {
+    let mut details = Vec::new();
+
+    for entry in WalkDir::new(module_dir).min_depth(1).sort_by_file_name() {
+        let entry = entry.map_err(|e| format!("读取 {} 失败: {}", module_dir.display(), e))?;
+        let path = entry
+            .path()
+            .strip_prefix(module_dir)
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        if path == module_constants::MODULE_MANIFEST_NAME {
+            continue;
+        }
+
+        let metadata = entry
+            .path()
+            .symlink_metadata()
+            .map_err(|e| format!("读取 {} 失败: {}", entry.path().display(), e))?;
+        let (kind, sha256, link) = if metadata.is_dir() {
+            (FileKind::Directory, String::new(), String::new())
+        } else if metadata.file_type().is_symlink() {
+            let link = fs::read_link(entry.path())
+                .map_err(|e| format!("读取 {} 失败: {}", entry.path().display(), e))?;
+            (
+                FileKind::Symlink,
+                String::new(),
+                link.to_string_lossy().to_string(),
+            )
+        } else {
+            (FileKind::File, sha256_file(entry.path())?, String::new())
+        };
+
+        details.push(FileDetail {
+            path,
+            kind,
+            size: if kind == FileKind::File {
+                metadata.len()
+            } else {
+                0
+            },
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mtime: metadata.mtime(),
+            sha256,
+            link,
+        });
+    }
+
+    Ok(details)
+}
+
+/// 计算文件内容的 SHA-256 值
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file =
+        fs::File::open(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// 根据打包的文件生成文件清单, 用于 deb 与 run 安装包
+pub fn manifest_from_package_files(metadata: &VersionMetaData, files: &[PackageFile]) -> Manifest {
+    let files = files
+        .iter()
+        .filter_map(|file| match &file.kind {
+            PackageFileKind::Directory => None,
+            PackageFileKind::File(contents) => Some(ManifestEntry {
+                path: file.relative_path.clone(),
+                kind: FileKind::File,
+                size: contents.len() as u64,
+                mode: file.mode,
+                sha256: sha256_hex(contents),
+                link: String::new(),
+            }),
+            PackageFileKind::Symlink(link) => Some(ManifestEntry {
+                path: file.relative_path.clone(),
+                kind: FileKind::Symlink,
+                size: 0,
+                mode: 0o777,
+                sha256: String::new(),
+                link: link.to_string_lossy().to_string(),
+            }),
+        })
+        .collect();
+
+    Manifest {
+        name: metadata.name.clone(),
+        version: metadata.version.clone(),
+        files,
+    }
+}
+
+/// 将文件清单写入模块安装目录, 用于未携带文件清单的安装方式
+/// # Arguments
+/// * `module_dir` - 模块安装目录
+/// * `manifest` - 文件清单
+/// # Return
+/// * Ok(()) - 写入成功
+/// * Err(String) - 写入失败的原因
+pub fn write_manifest(module_dir: &Path, manifest: &Manifest) -> Result<(), String> {
+    let path = manifest_path(module_dir);
+    fs::write(&path, manifest.to_toml()?)
+        .map_err(|e| format!("写入文件清单 {} 失败: {}", path.display(), e))
+}
+
+/// 校验已安装模块的文件是否与文件清单一致
+/// # Arguments
+/// * `module_dir` - 模块安装目录
+/// # Return
+/// * Ok(Vec<ManifestDiff>) - 差异列表, 为空时表示一致
+/// * Err(String) - 文件清单不存在、解析失败或扫描失败
+pub fn verify_module(module_dir: &Path) -> Result<Vec<ManifestDiff>, String> {
+    let manifest = read_manifest(module_dir)?;
+    let details = scan_module_files(module_dir)?;
+    Ok(compare_manifest(&manifest, &details))
+}
+
+/// 读取模块的文件清单
+/// # Arguments
+/// * `module_dir` - 模块安装目录
+/// # Return
+/// * Ok(Manifest) - 文件清单
+/// * Err(String) - 文件清单不存在或解析失败
+pub fn read_manifest(module_dir: &Path) -> Result<Manifest, String> {
+    let path = manifest_path(module_dir);
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("读取文件清单 {} 失败: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("解析文件清单 {} 失败: {}", path.display(), e))
+}
+
+/// 比较模块安装目录中的实际文件与文件清单
+/// # Arguments
+/// * `manifest` - 文件清单
+/// * `details` - 实际文件列表
+/// # Return
+/// * 差异列表, 为空时表示文件与清单一致
+pub fn compare_manifest(manifest: &Manifest, details: &[FileDetail]) -> Vec<ManifestDiff> {
+    let mut diffs = Vec::new();
+    let actual_files: HashMap<&str, &FileDetail> = details
+        .iter()
+        .map(|detail| (detail.path.as_str(), detail))
+        .collect();
+    let expected_paths: HashSet<&str> = manifest
+        .files
+        .iter()
+        .map(|expected| expected.path.as_str())
+        .collect();
+
+    for expected in &manifest.files {
+        let Some(actual) = actual_files.get(expected.path.as_str()) else {
+            diffs.push(ManifestDiff {
+                path: expected.path.clone(),
+                kind: DiffKind::Missing,
+                detail: "文件不存在".to_string(),
+            });
+            continue;
+        };
+
+        let mut changes = Vec::new();
+        if actual.kind != expected.kind {
+            changes.push("文件类型变化".to_string());
+        } else if expected.kind == FileKind::File && actual.sha256 != expected.sha256 {
+            changes.push(format!(
+                "内容变化, 大小 {} -> {}",
+                expected.size, actual.size
+            ));
+        } else if expected.kind == FileKind::Symlink && actual.link != expected.link {
+            changes.push(format!("链接变化 {} -> {}", expected.link, actual.link));
+        }
+        if expected.kind != FileKind::Symlink && actual.mode != expected.mode {
+            changes.push(format!("权限 {:04o} -> {:04o}", expected.mode, actual.mode));
+        }
+        if !changes.is_empty() {
+            diffs.push(ManifestDiff {
+                path: expected.path.clone(),
+                kind: DiffKind::Modified,
+                detail: changes.join(", "),
+            });
+        }
+    }
+
+    for actual in details {
+        if actual.kind != FileKind::Directory && !expected_paths.contains(actual.path.as_str()) {
+            diffs.push(ManifestDiff {
+                path: actual.path.clone(),
+                kind: DiffKind::Extra,
+                detail: "文件不在清单中".to_string(),
+            });
+        }
+    }
+
+    diffs
+}
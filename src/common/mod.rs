@@ -3,4 +3,22 @@
  * Date: 24-7-31
  * File: mod
  */
+pub mod backup;
+#[allow(clippy::module_inception)]
 pub mod common;
+pub mod config;
+pub mod deb;
+pub mod depends;
+pub mod graph;
+pub mod health;
+pub mod logfile;
+pub mod manifest;
+pub mod output;
+pub mod package;
+pub mod program;
+pub mod protocol;
+pub mod resources;
+pub mod run;
+pub mod schema;
+pub mod supervisor;
+pub mod version;
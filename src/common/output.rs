@@ -0,0 +1,110 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-22
+ * File: output
+ */
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// 表格输出, 供人阅读, 其余格式供脚本解析
+pub const TABLE: &str = "table";
+
+/// 检查输出格式是否为表格
+pub fn is_table(format: &str) -> bool {
+    format == TABLE
+}
+
+/// 将数据序列化为指定的格式
+/// # Arguments
+/// * `format` - 输出格式, json/yaml/toml/csv
+/// * `key` - 数据为列表时在 toml 中使用的键名, toml 的顶层必须是表
+/// * `data` - 单条记录或记录列表
+/// # Return
+/// * Ok(String) - 序列化后的文本
+/// * Err(String) - 格式不支持或序列化失败
+pub fn render<T: Serialize + ?Sized>(format: &str, key: &str, data: &T) -> Result<String, String> {
+    let value = serde_json::to_value(data).map_err(|e| format!("数据序列化失败: {}", e))?;
+    match format {
+        "json" => {
+            serde_json::to_string_pretty(&value).map_err(|e| format!("JSON 序列化失败: {}", e))
+        }
+        "yaml" => serde_yaml::to_string(&value).map_err(|e| format!("YAML 序列化失败: {}", e)),
+        "toml" => {
+            let value = match value {
+                Value::Array(_) => serde_json::json!({ key: value }),
+                value => value,
+            };
+            toml::to_string(&value).map_err(|e| format!("TOML 序列化失败: {}", e))
+        }
+        "csv" => render_csv(&value),
+        _ => Err(format!("不支持的输出格式: {}", format)),
+    }
+}
+
+/// 按指定的格式输出数据, 序列化失败时以非 0 状态码退出
+pub fn print<T: Serialize + ?Sized>(format: &str, key: &str, data: &T) {
+    match render(format, key, data) {
+        Ok(text) => println!("{}", text.trim_end()),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// 输出错误信息, 非表格输出时写入 stderr, 避免混入供脚本解析的数据
+/// # Arguments
+/// * `format` - 输出格式
+/// * `message` - 错误信息
+pub fn print_error(format: &str, message: &str) {
+    if is_table(format) {
+        crate::print_error_msg!("{}", message);
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+/// 将记录序列化为 csv, 表头为第一条记录的字段名, 列表字段以 ';' 连接
+fn render_csv(value: &Value) -> Result<String, String> {
+    let records = match value {
+        Value::Array(records) => records.clone(),
+        record => vec![record.clone()],
+    };
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut header: Vec<String> = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        let Value::Object(fields) = record else {
+            return Err("CSV 只支持由对象组成的记录".to_string());
+        };
+        if index == 0 {
+            header = fields.keys().cloned().collect();
+            writer
+                .write_record(&header)
+                .map_err(|e| format!("CSV 序列化失败: {}", e))?;
+        }
+        let row: Vec<String> = header
+            .iter()
+            .map(|name| fields.get(name).map(csv_field).unwrap_or_default())
+            .collect();
+        writer
+            .write_record(&row)
+            .map_err(|e| format!("CSV 序列化失败: {}", e))?;
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| format!("CSV 序列化失败: {}", e))?;
+    String::from_utf8(bytes).map_err(|e| format!("CSV 序列化失败: {}", e))
+}
+
+/// 将单个字段转换为 csv 中的文本
+fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(csv_field).collect::<Vec<_>>().join(";"),
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,321 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-15
+ * File: package
+ */
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, EntryType, Header};
+use walkdir::WalkDir;
+
+use crate::common::common::{module_constants, read_version_meta_data, VersionMetaData};
+use crate::common::{manifest, schema, version};
+use crate::print_warning_msg;
+
+/// 维护脚本名称, 与 Debian 软件包的维护脚本保持一致
+pub const MAINTAINER_SCRIPTS: [&str; 4] = ["preinst", "postinst", "prerm", "postrm"];
+
+/// 打包时需要写入的文件
+pub struct PackageFile {
+    /// 安装后的相对路径, 相对于模块安装目录
+    pub relative_path: String,
+
+    /// 文件权限
+    pub mode: u32,
+
+    /// 文件修改时间
+    pub mtime: u64,
+
+    /// 文件类型
+    pub kind: PackageFileKind,
+}
+
+/// 打包文件的类型
+pub enum PackageFileKind {
+    Directory,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+/// 收集模块安装目录树中的所有文件, 并追加模块元数据文件以及文件清单,
+/// 模块名称与版本号会写入安装路径和安装脚本, 收集前先检查其格式
+pub fn collect_package_files(
+    metadata: &VersionMetaData,
+    install_dir: &Path,
+) -> Result<Vec<PackageFile>, String> {
+    schema::validate_module_name(&metadata.name)?;
+    version::parse_version(&metadata.version)?;
+
+    let mut files = Vec::new();
+
+    if install_dir.is_dir() {
+        for entry in WalkDir::new(install_dir).min_depth(1).sort_by_file_name() {
+            let entry = entry.map_err(|e| format!("读取 {} 失败: {}", install_dir.display(), e))?;
+            let relative_path = entry
+                .path()
+                .strip_prefix(install_dir)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            // 元数据文件以当前模块的配置为准, 文件清单在打包时重新生成
+            if relative_path == module_constants::MODULE_META_DATA_NAME
+                || relative_path == module_constants::MODULE_MANIFEST_NAME
+            {
+                continue;
+            }
+
+            let file_metadata = entry
+                .path()
+                .symlink_metadata()
+                .map_err(|e| format!("读取 {} 失败: {}", entry.path().display(), e))?;
+            let kind = if file_metadata.is_dir() {
+                PackageFileKind::Directory
+            } else if file_metadata.file_type().is_symlink() {
+                PackageFileKind::Symlink(fs::read_link(entry.path()).map_err(io_error)?)
+            } else {
+                PackageFileKind::File(
+                    fs::read(entry.path())
+                        .map_err(|e| format!("读取 {} 失败: {}", entry.path().display(), e))?,
+                )
+            };
+            files.push(PackageFile {
+                relative_path,
+                mode: file_metadata.mode() & 0o7777,
+                mtime: file_metadata.mtime().max(0) as u64,
+                kind,
+            });
+        }
+    } else {
+        return Err(format!("模块安装目录 {} 不存在", install_dir.display()));
+    }
+
+    // 构建信息只写入软件包中的元数据, 模块源码中的 version.toml 保持不变
+    let mut metadata = metadata.clone();
+    apply_build_info(&mut metadata);
+    let metadata = &metadata;
+    let meta_contents = toml::to_string(metadata).map_err(|e| e.to_string())?;
+    files.push(PackageFile {
+        relative_path: module_constants::MODULE_META_DATA_NAME.to_string(),
+        mode: 0o644,
+        mtime: now(),
+        kind: PackageFileKind::File(meta_contents.into_bytes()),
+    });
+
+    let manifest_contents = manifest::manifest_from_package_files(metadata, &files).to_toml()?;
+    files.push(PackageFile {
+        relative_path: module_constants::MODULE_MANIFEST_NAME.to_string(),
+        mode: 0o644,
+        mtime: now(),
+        kind: PackageFileKind::File(manifest_contents.into_bytes()),
+    });
+
+    Ok(files)
+}
+
+/// 将 module build 记录在构建目录中的 git 提交与构建时间写入元数据, 打包在模块目录下执行.
+/// 模块尚未构建或构建时的名称、版本号与当前不一致时保留 version.toml 中的值
+/// # Arguments
+/// * `metadata` - 写入软件包的模块元数据
+fn apply_build_info(metadata: &mut VersionMetaData) {
+    let path =
+        Path::new(module_constants::BUILD_DIR_NAME).join(module_constants::MODULE_META_DATA_NAME);
+    if !path.exists() {
+        print_warning_msg!(
+            "没有找到 {}, 软件包中不记录构建信息, 请先执行 module build",
+            path.display()
+        );
+        return;
+    }
+    match read_version_meta_data(&path) {
+        Ok(built) if built.name == metadata.name && built.version == metadata.version => {
+            metadata.git_commit = built.git_commit;
+            metadata.build_time = built.build_time;
+        }
+        Ok(built) => print_warning_msg!(
+            "{} 中记录的是 {} {} 的构建信息, 软件包中不记录构建信息, 请重新执行 module build",
+            path.display(),
+            built.name,
+            built.version
+        ),
+        Err(err) => print_warning_msg!("{}", err),
+    }
+}
+
+/// 向 tar 归档中追加目录
+pub fn append_directory<W: Write>(
+    builder: &mut Builder<W>,
+    path: &str,
+    mode: u32,
+    mtime: u64,
+) -> io::Result<()> {
+    let mut header = new_header(EntryType::Directory, mode, mtime);
+    header.set_size(0);
+    builder.append_data(&mut header, path, io::empty())
+}
+
+/// 向 tar 归档中追加普通文件
+pub fn append_file<W: Write>(
+    builder: &mut Builder<W>,
+    path: &str,
+    mode: u32,
+    mtime: u64,
+    contents: &[u8],
+) -> io::Result<()> {
+    let mut header = new_header(EntryType::Regular, mode, mtime);
+    header.set_size(contents.len() as u64);
+    builder.append_data(&mut header, path, contents)
+}
+
+/// 向 tar 归档中追加符号链接
+pub fn append_symlink<W: Write>(
+    builder: &mut Builder<W>,
+    path: &str,
+    link: &Path,
+    mtime: u64,
+) -> io::Result<()> {
+    let mut header = new_header(EntryType::Symlink, 0o777, mtime);
+    header.set_size(0);
+    builder.append_link(&mut header, path, link)
+}
+
+/// 创建属于 root 用户的 tar 文件头
+fn new_header(entry_type: EntryType, mode: u32, mtime: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_username("root").ok();
+    header.set_groupname("root").ok();
+    header.set_mtime(mtime);
+    header
+}
+
+/// 读取 deb 或 run 安装包在打包时生成的文件清单, 文件清单中记录了安装包内每个文件的校验值
+/// # Arguments
+/// * `file_name` - 安装包文件名称, 根据扩展名判断格式
+/// * `contents` - 安装包内容
+/// # Return
+/// * Ok(Vec<Manifest>) - 安装包中模块的文件清单
+/// * Err(String) - 格式不支持或解析失败的原因
+pub fn read_package_manifests(
+    file_name: &str,
+    contents: &[u8],
+) -> Result<Vec<manifest::Manifest>, String> {
+    let data = if file_name.ends_with(".deb") {
+        deb_data(contents)?
+    } else if file_name.ends_with(".run") {
+        run_payload(contents)?
+    } else {
+        return Err(format!("{} 不是 deb 或 run 安装包", file_name));
+    };
+
+    // deb 中的文件清单位于 <name>/<version>/manifest.toml, 与清单中记录的名称和版本号一致时才采用,
+    // 避免把模块自身携带的同名文件当作文件清单, run 中的文件清单位于 module/manifest.toml
+    let mut manifests = Vec::new();
+    let mut archive = Archive::new(GzDecoder::new(data));
+    for entry in archive.entries().map_err(io_error)? {
+        let mut entry = entry.map_err(io_error)?;
+        let path = entry
+            .path()
+            .map_err(io_error)?
+            .to_string_lossy()
+            .to_string();
+        if !path.ends_with(&format!("/{}", module_constants::MODULE_MANIFEST_NAME)) {
+            continue;
+        }
+        let mut text = String::new();
+        entry.read_to_string(&mut text).map_err(io_error)?;
+        let Ok(manifest) = toml::from_str::<manifest::Manifest>(&text) else {
+            continue;
+        };
+        let expected = format!(
+            "/{}/{}/{}",
+            manifest.name,
+            manifest.version,
+            module_constants::MODULE_MANIFEST_NAME
+        );
+        if path.ends_with(&expected) || path == "module/manifest.toml" {
+            manifests.push(manifest);
+        }
+    }
+    Ok(manifests)
+}
+
+/// 获取 deb 安装包中 data.tar.gz 的内容
+fn deb_data(contents: &[u8]) -> Result<&[u8], String> {
+    let mut rest = contents
+        .strip_prefix(b"!<arch>\n".as_slice())
+        .ok_or("不是 ar 格式的 deb 安装包")?;
+    // ar 成员由 60 字节的文件头与数据组成, 数据按 2 字节对齐
+    while rest.len() >= 60 {
+        let name = String::from_utf8_lossy(&rest[..16]).trim_end().to_string();
+        let size: usize = String::from_utf8_lossy(&rest[48..58])
+            .trim()
+            .parse()
+            .map_err(|_| "deb 安装包的成员大小无效".to_string())?;
+        let data = rest
+            .get(60..60 + size)
+            .ok_or("deb 安装包不完整".to_string())?;
+        match name.trim_end_matches('/') {
+            "data.tar.gz" => return Ok(data),
+            name if name.starts_with("data.tar") => {
+                return Err(format!("不支持的 deb 数据压缩格式 {}", name))
+            }
+            _ => {}
+        }
+        rest = rest.get(60 + size + size % 2..).unwrap_or_default();
+    }
+    Err("deb 安装包中没有 data.tar.gz".to_string())
+}
+
+/// 获取 run 安装包中脚本之后的 tar.gz 数据
+fn run_payload(contents: &[u8]) -> Result<&[u8], String> {
+    let script = String::from_utf8_lossy(&contents[..contents.len().min(64 * 1024)]);
+    let payload_line: usize = script
+        .lines()
+        .find_map(|line| line.strip_prefix("PAYLOAD_LINE="))
+        .and_then(|line| line.trim().parse().ok())
+        .ok_or("run 安装包中没有 PAYLOAD_LINE".to_string())?;
+    let skip_lines = payload_line
+        .checked_sub(1)
+        .ok_or("run 安装包中的 PAYLOAD_LINE 必须大于 0".to_string())?;
+    let offset = contents
+        .split_inclusive(|b| *b == b'\n')
+        .take(skip_lines)
+        .map(<[u8]>::len)
+        .sum();
+    Ok(&contents[offset..])
+}
+
+/// 计算数据的 SHA-256 值, 以十六进制字符串返回
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 将字符串转义为 shell 的单引号字符串, 其中的 ' 替换为 '\''
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// 当前时间戳(秒)
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn io_error(e: io::Error) -> String {
+    e.to_string()
+}
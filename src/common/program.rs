@@ -0,0 +1,717 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-25
+ * File: program
+ */
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::common::common::{self, module_constants};
+use crate::common::graph;
+use crate::common::health::{HealthCheck, Probe};
+use crate::common::logfile::LogConfig;
+use crate::common::resources::Resources;
+use crate::common::schema::{self, SchemaIssue};
+
+/// 程序退出后的重启策略
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// 无论退出码如何都重启
+    Always,
+
+    /// 退出码非 0 或被信号结束时重启
+    #[default]
+    OnFailure,
+
+    /// 不重启
+    Never,
+}
+
+/// 自动重启前的等待时间, 按连续失败的次数指数增长, 对应 supervise.toml 中的 [backoff]
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Backoff {
+    /// 第一次重启前等待的秒数
+    #[serde(default = "default_backoff_initial")]
+    pub initial: f64,
+
+    /// 等待时间的上限(秒)
+    #[serde(default = "default_backoff_max")]
+    pub max: f64,
+
+    /// 每次连续失败后等待时间的倍数
+    #[serde(default = "default_backoff_multiplier")]
+    pub multiplier: f64,
+
+    /// 等待时间随机浮动的比例, 避免多个程序同时重启, ex: 0.2 表示 ±20%
+    #[serde(default = "default_backoff_jitter")]
+    pub jitter: f64,
+}
+
+/// 重启前等待时间的上限(秒), [backoff] 的 max 不能超过该值
+pub const MAX_BACKOFF_SECONDS: f64 = 86400.0;
+
+fn default_backoff_initial() -> f64 {
+    1.0
+}
+
+fn default_backoff_max() -> f64 {
+    60.0
+}
+
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_backoff_jitter() -> f64 {
+    0.2
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: default_backoff_initial(),
+            max: default_backoff_max(),
+            multiplier: default_backoff_multiplier(),
+            jitter: default_backoff_jitter(),
+        }
+    }
+}
+
+/// 频繁崩溃的判定, 对应 supervise.toml 中的 [crash_loop], 判定后程序进入 fatal 状态,
+/// 不再自动重启, 直到手动执行 supervisord restart
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct CrashLoop {
+    /// window 秒内失败多少次判定为频繁崩溃, 启动失败、意外退出与存活检查失败都计为一次失败
+    #[serde(default = "default_crash_loop_max_failures")]
+    pub max_failures: u32,
+
+    /// 统计失败次数的时间窗口(秒), 程序持续运行超过该时长后连续失败的次数清零
+    #[serde(default = "default_crash_loop_window")]
+    pub window: u64,
+
+    /// 判定为频繁崩溃时执行的命令, 路径规则与程序的 command 相同, 为空时不执行,
+    /// 通过 WAYTOUS_PROGRAM、WAYTOUS_FAILURES、WAYTOUS_MESSAGE 环境变量获取程序名称、失败次数与原因
+    #[serde(default)]
+    pub hook: String,
+
+    /// hook 的参数
+    #[serde(default)]
+    pub hook_args: Vec<String>,
+}
+
+fn default_crash_loop_max_failures() -> u32 {
+    5
+}
+
+fn default_crash_loop_window() -> u64 {
+    60
+}
+
+impl Default for CrashLoop {
+    fn default() -> Self {
+        CrashLoop {
+            max_failures: default_crash_loop_max_failures(),
+            window: default_crash_loop_window(),
+            hook: String::new(),
+            hook_args: Vec::new(),
+        }
+    }
+}
+
+/// 由 supervisord 管理的程序, 定义在模块当前版本目录下的 supervise.toml 中
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Program {
+    /// 程序名称, 与模块名称相同
+    #[serde(skip)]
+    pub name: String,
+
+    /// 模块当前版本的目录
+    #[serde(skip)]
+    pub module_dir: PathBuf,
+
+    /// 启动命令, 包含 '/' 的相对路径相对于模块目录, 否则在 PATH 中查找
+    pub command: String,
+
+    /// 启动参数
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// 工作目录, 相对路径相对于模块目录, 为空时为模块目录
+    #[serde(default)]
+    pub directory: String,
+
+    /// 追加的环境变量
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+
+    /// 运行程序的用户, 为空时与 supervisord 相同
+    #[serde(default)]
+    pub user: String,
+
+    /// 所属的分组, 用于批量启动或停止, ex: sensors, planning
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// 需要在这些程序就绪后再启动, 停止时先于这些程序停止, 只影响顺序
+    #[serde(default)]
+    pub after: Vec<String>,
+
+    /// 依赖的程序, 启动时一并启动并等待其就绪, 依赖未就绪时不启动, 同时隐含 after
+    #[serde(default)]
+    pub requires: Vec<String>,
+
+    /// supervisord 启动时是否自动启动该程序
+    #[serde(default = "default_autostart")]
+    pub autostart: bool,
+
+    /// 重启策略
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    /// 自动重启前的等待时间
+    #[serde(default)]
+    pub backoff: Backoff,
+
+    /// 频繁崩溃的判定
+    #[serde(default)]
+    pub crash_loop: CrashLoop,
+
+    /// 程序启动后需要持续运行的秒数, 超过后视为启动成功;
+    /// 配置了就绪检查时为等待就绪检查成功的最长秒数, 超时视为启动失败
+    #[serde(default = "default_start_timeout")]
+    pub start_timeout: u64,
+
+    /// 停止程序时发送的信号, ex: TERM, INT, SIGQUIT
+    #[serde(default = "default_stop_signal")]
+    pub stop_signal: String,
+
+    /// 发送停止信号后等待程序退出的秒数, 超时后发送 SIGKILL
+    #[serde(default = "default_stop_grace_period")]
+    pub stop_grace_period: u64,
+
+    /// 就绪检查, 成功后程序才进入 running 状态, 依赖它的程序才会启动
+    #[serde(default)]
+    pub readiness: Option<HealthCheck>,
+
+    /// 存活检查, 连续失败后重启程序
+    #[serde(default)]
+    pub liveness: Option<HealthCheck>,
+
+    /// 标准输出与标准错误的保存方式
+    #[serde(default)]
+    pub log: LogConfig,
+
+    /// CPU 亲和性、调度与内存等资源限制
+    #[serde(default)]
+    pub resources: Resources,
+}
+
+fn default_autostart() -> bool {
+    true
+}
+
+fn default_start_timeout() -> u64 {
+    1
+}
+
+fn default_stop_signal() -> String {
+    "TERM".to_string()
+}
+
+fn default_stop_grace_period() -> u64 {
+    10
+}
+
+/// 用于定位字段位置的程序定义, 只包含需要校验内容的字段
+#[derive(Deserialize)]
+struct SpannedProgram {
+    command: Spanned<String>,
+    directory: Option<Spanned<String>>,
+    environment: Option<BTreeMap<Spanned<String>, String>>,
+    user: Option<Spanned<String>>,
+    groups: Option<Vec<Spanned<String>>>,
+    after: Option<Vec<Spanned<String>>>,
+    requires: Option<Vec<Spanned<String>>>,
+    start_timeout: Option<Spanned<u64>>,
+    stop_signal: Option<Spanned<String>>,
+    stop_grace_period: Option<Spanned<u64>>,
+    readiness: Option<Spanned<HealthCheck>>,
+    liveness: Option<Spanned<HealthCheck>>,
+    log: Option<Spanned<LogConfig>>,
+    backoff: Option<Spanned<Backoff>>,
+    crash_loop: Option<Spanned<CrashLoop>>,
+    resources: Option<Spanned<Resources>>,
+}
+
+/// 所有已知的字段, 其余字段会给出提示
+const KNOWN_FIELDS: [&str; 19] = [
+    "command",
+    "args",
+    "directory",
+    "environment",
+    "user",
+    "groups",
+    "after",
+    "requires",
+    "autostart",
+    "restart",
+    "backoff",
+    "crash_loop",
+    "start_timeout",
+    "stop_signal",
+    "stop_grace_period",
+    "readiness",
+    "liveness",
+    "log",
+    "resources",
+];
+
+/// 支持的停止信号
+const SIGNALS: [(&str, libc::c_int); 7] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+];
+
+impl Program {
+    /// 启动命令的完整路径
+    pub fn command_path(&self) -> PathBuf {
+        if self.command.contains('/') {
+            self.module_dir.join(&self.command)
+        } else {
+            PathBuf::from(&self.command)
+        }
+    }
+
+    /// 工作目录的完整路径
+    pub fn working_dir(&self) -> PathBuf {
+        self.module_dir.join(&self.directory)
+    }
+
+    /// 启动顺序上依赖的程序, 包括 after 与 requires
+    pub fn dependencies(&self) -> impl Iterator<Item = &String> {
+        self.after.iter().chain(&self.requires)
+    }
+
+    /// 频繁崩溃时执行的命令的完整路径, 没有配置时为空
+    pub fn hook_path(&self) -> Option<PathBuf> {
+        let hook = &self.crash_loop.hook;
+        if hook.is_empty() {
+            None
+        } else if hook.contains('/') {
+            Some(self.module_dir.join(hook))
+        } else {
+            Some(PathBuf::from(hook))
+        }
+    }
+
+    /// 停止信号的值, 定义已经过校验, 无法识别时使用 SIGTERM
+    pub fn stop_signal_number(&self) -> libc::c_int {
+        parse_signal(&self.stop_signal).unwrap_or(libc::SIGTERM)
+    }
+}
+
+/// 解析信号名称, 不区分大小写, 可以带 SIG 前缀
+/// # Arguments
+/// * `name` - 信号名称, ex: TERM, sigint
+/// # Return
+/// * Ok(c_int) - 信号值
+/// * Err(String) - 不支持的信号
+pub fn parse_signal(name: &str) -> Result<libc::c_int, String> {
+    let upper = name.to_uppercase();
+    let short = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(signal, _)| *signal == short)
+        .map(|(_, value)| *value)
+        .ok_or(format!(
+            "不支持的信号 {}, 可选值: {}",
+            name,
+            SIGNALS.map(|(signal, _)| signal).join(", ")
+        ))
+}
+
+/// 获取模块的程序定义文件路径
+pub fn program_path(root: &Path, name: &str) -> PathBuf {
+    common::get_module_active_dir(root, name).join(module_constants::SUPERVISE_FILE_NAME)
+}
+
+/// 读取单个模块的程序定义, 定义中有错误时读取失败
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `name` - 模块名称
+/// # Return
+/// * Ok(Some(Program)) - 程序定义
+/// * Ok(None) - 模块没有提供 supervise.toml
+/// * Err(String) - 读取失败或定义有错误
+pub fn load_program(root: &Path, name: &str) -> Result<Option<Program>, String> {
+    // 程序名称即模块名称, 会用于拼接日志与 cgroup 的路径
+    schema::validate_module_name(name)?;
+    let module_dir = common::get_module_active_dir(root, name);
+    let path = module_dir.join(module_constants::SUPERVISE_FILE_NAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    let errors: Vec<String> = validate_program(&module_dir, &contents)
+        .into_iter()
+        .filter(|issue| issue.level == "error")
+        .map(|issue| {
+            format!(
+                "{}:{}:{}: {}",
+                path.display(),
+                issue.line,
+                issue.column,
+                issue.message
+            )
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors.join("; "));
+    }
+    let mut program: Program =
+        toml::from_str(&contents).map_err(|e| format!("解析 {} 失败: {}", path.display(), e))?;
+    program.name = name.to_string();
+    program.module_dir = module_dir;
+    Ok(Some(program))
+}
+
+/// 读取所有已安装模块的程序定义
+/// # Arguments
+/// * `root` - 安装根目录
+/// # Return
+/// * (Vec<Program>, Vec<(String, String)>) - 程序定义, 以及读取失败的 (模块名称, 原因)
+pub fn load_programs(root: &Path) -> (Vec<Program>, Vec<(String, String)>) {
+    let mut programs = Vec::new();
+    let mut errors = Vec::new();
+    for name in common::get_installed_module_list(root) {
+        match load_program(root, &name) {
+            Ok(Some(program)) => programs.push(program),
+            Ok(None) => {}
+            Err(err) => errors.push((name, err)),
+        }
+    }
+    (programs, errors)
+}
+
+/// 校验 supervise.toml 的内容, 问题按出现的位置排序, 程序之间的依赖关系由 validate_relations 校验
+/// # Arguments
+/// * `module_dir` - 模块当前版本的目录, 用于检查命令与工作目录
+/// * `contents` - supervise.toml 的内容
+/// # Return
+/// * Vec<SchemaIssue> - 发现的问题, 为空表示校验通过
+pub fn validate_program(module_dir: &Path, contents: &str) -> Vec<SchemaIssue> {
+    let mut issues = Vec::new();
+    let mut error = |offset: usize, message: String| {
+        let (line, column) = schema::line_column(contents, offset);
+        issues.push(SchemaIssue {
+            level: "error",
+            line,
+            column,
+            message,
+        });
+    };
+
+    // 语法错误、缺少字段与字段类型错误在这一步发现
+    if let Err(e) = toml::from_str::<Program>(contents) {
+        error(
+            e.span().map(|span| span.start).unwrap_or(0),
+            e.message().to_string(),
+        );
+        return issues;
+    }
+    let Ok(spanned) = toml::from_str::<SpannedProgram>(contents) else {
+        return issues;
+    };
+
+    let command = spanned.command.get_ref();
+    if command.trim().is_empty() {
+        error(spanned.command.span().start, "command 不能为空".to_string());
+    } else if command.contains('/') {
+        let path = module_dir.join(command);
+        let executable = fs::metadata(&path)
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false);
+        if !executable {
+            error(
+                spanned.command.span().start,
+                format!("{} 不存在或不可执行", path.display()),
+            );
+        }
+    } else if !in_search_path(command) {
+        error(
+            spanned.command.span().start,
+            format!("在 PATH 中找不到命令 {}", command),
+        );
+    }
+
+    if let Some(directory) = &spanned.directory {
+        let path = module_dir.join(directory.get_ref());
+        if !path.is_dir() {
+            error(
+                directory.span().start,
+                format!("工作目录 {} 不存在", path.display()),
+            );
+        }
+    }
+    for name in spanned.environment.iter().flat_map(|env| env.keys()) {
+        if name.get_ref().is_empty() || name.get_ref().contains('=') {
+            error(
+                name.span().start,
+                format!("环境变量名 {} 不合法", name.get_ref()),
+            );
+        }
+    }
+    if let Some(user) = &spanned.user {
+        if !user.get_ref().is_empty() && common::user_ids(user.get_ref()).is_none() {
+            error(user.span().start, format!("用户 {} 不存在", user.get_ref()));
+        }
+    }
+    for group in spanned.groups.iter().flatten() {
+        let name = group.get_ref();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "*?[]".contains(c)) {
+            error(
+                group.span().start,
+                format!("分组名称 {} 不能为空, 也不能包含空白或通配符", name),
+            );
+        }
+    }
+    for name in [&spanned.after, &spanned.requires]
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        if name.get_ref().trim().is_empty() {
+            error(name.span().start, "依赖的程序名称不能为空".to_string());
+        }
+    }
+    for seconds in [&spanned.start_timeout, &spanned.stop_grace_period]
+        .into_iter()
+        .flatten()
+    {
+        if *seconds.get_ref() == 0 {
+            error(seconds.span().start, "时长必须大于 0 秒".to_string());
+        }
+    }
+    if let Some(signal) = &spanned.stop_signal {
+        if let Err(err) = parse_signal(signal.get_ref()) {
+            error(signal.span().start, err);
+        }
+    }
+    for check in [&spanned.readiness, &spanned.liveness]
+        .into_iter()
+        .flatten()
+    {
+        for message in check.get_ref().validate() {
+            error(check.span().start, message);
+        }
+        if let Probe::Exec { command, .. } = &check.get_ref().probe {
+            if command.contains('/') && !module_dir.join(command).is_file() {
+                error(
+                    check.span().start,
+                    format!("检查命令 {} 不存在", module_dir.join(command).display()),
+                );
+            }
+        }
+    }
+    if let Some(resources) = &spanned.resources {
+        for message in resources.get_ref().validate() {
+            error(resources.span().start, message);
+        }
+    }
+    if let Some(log) = &spanned.log {
+        if log.get_ref().max_size == 0 {
+            error(log.span().start, "max_size 必须大于 0 MB".to_string());
+        }
+    }
+    if let Some(backoff) = &spanned.backoff {
+        let value = backoff.get_ref();
+        let mut invalid = Vec::new();
+        let fields = [
+            ("initial", value.initial),
+            ("max", value.max),
+            ("multiplier", value.multiplier),
+            ("jitter", value.jitter),
+        ];
+        for (name, field) in fields {
+            if !field.is_finite() {
+                invalid.push(format!("{} 必须是有限的数值", name));
+            }
+        }
+        if invalid.is_empty() {
+            if value.initial <= 0.0 {
+                invalid.push("initial 必须大于 0 秒".to_string());
+            }
+            if value.max < value.initial {
+                invalid.push("max 不能小于 initial".to_string());
+            }
+            if value.max > MAX_BACKOFF_SECONDS {
+                invalid.push(format!("max 不能超过 {} 秒", MAX_BACKOFF_SECONDS));
+            }
+            if value.multiplier < 1.0 {
+                invalid.push("multiplier 不能小于 1".to_string());
+            }
+            if !(0.0..=1.0).contains(&value.jitter) {
+                invalid.push("jitter 必须在 0 到 1 之间".to_string());
+            }
+        }
+        for message in invalid {
+            error(backoff.span().start, message);
+        }
+    }
+    if let Some(crash_loop) = &spanned.crash_loop {
+        let value = crash_loop.get_ref();
+        if value.max_failures == 0 {
+            error(
+                crash_loop.span().start,
+                "max_failures 必须大于 0".to_string(),
+            );
+        }
+        if value.window == 0 {
+            error(crash_loop.span().start, "window 必须大于 0 秒".to_string());
+        }
+        if value.hook.contains('/') && !module_dir.join(&value.hook).is_file() {
+            error(
+                crash_loop.span().start,
+                format!("hook {} 不存在", module_dir.join(&value.hook).display()),
+            );
+        }
+    }
+
+    if let Ok(keys) = toml::from_str::<BTreeMap<Spanned<String>, toml::Value>>(contents) {
+        for key in keys.keys() {
+            if !KNOWN_FIELDS.contains(&key.get_ref().as_str()) {
+                let (line, column) = schema::line_column(contents, key.span().start);
+                issues.push(SchemaIssue {
+                    level: "warning",
+                    line,
+                    column,
+                    message: format!("未知字段 {}, 将被忽略", key.get_ref()),
+                });
+            }
+        }
+    }
+
+    issues.sort_by_key(|issue| (issue.line, issue.column));
+    issues
+}
+
+/// 用于定位依赖关系的程序定义
+#[derive(Deserialize)]
+struct SpannedRelations {
+    #[serde(default)]
+    after: Vec<Spanned<String>>,
+
+    #[serde(default)]
+    requires: Vec<Spanned<String>>,
+}
+
+/// 校验所有程序之间的依赖关系: requires 必须指向存在的程序, after 指向不存在的程序时给出提示,
+/// 依赖关系中不能存在循环
+/// # Arguments
+/// * `contents` - 所有程序定义的内容, key 为模块名称
+/// # Return
+/// * BTreeMap<String, Vec<SchemaIssue>> - 每个模块的问题, 没有问题的模块不在其中
+pub fn validate_relations(
+    contents: &BTreeMap<String, String>,
+) -> BTreeMap<String, Vec<SchemaIssue>> {
+    let relations: BTreeMap<&String, SpannedRelations> = contents
+        .iter()
+        .filter_map(|(name, contents)| Some((name, toml::from_str(contents).ok()?)))
+        .collect();
+    let mut issues: BTreeMap<String, Vec<SchemaIssue>> = BTreeMap::new();
+    let mut report = |name: &str, offset: usize, level: &'static str, message: String| {
+        let (line, column) = schema::line_column(&contents[name], offset);
+        issues
+            .entry(name.to_string())
+            .or_default()
+            .push(SchemaIssue {
+                level,
+                line,
+                column,
+                message,
+            });
+    };
+
+    let mut graph = graph::DependencyGraph::new();
+    for (name, relation) in &relations {
+        for depend in &relation.requires {
+            if !relations.contains_key(depend.get_ref()) {
+                report(
+                    name,
+                    depend.span().start,
+                    "error",
+                    format!("依赖的程序 {} 不存在", depend.get_ref()),
+                );
+            }
+        }
+        for depend in &relation.after {
+            if !relations.contains_key(depend.get_ref()) {
+                report(
+                    name,
+                    depend.span().start,
+                    "warning",
+                    format!("after 中的程序 {} 不存在, 将被忽略", depend.get_ref()),
+                );
+            }
+        }
+        graph.insert(
+            name.to_string(),
+            relation
+                .after
+                .iter()
+                .chain(&relation.requires)
+                .map(|depend| depend.get_ref().clone())
+                .collect(),
+        );
+    }
+
+    // 每次找到一个循环后去掉其中的一条边, 直到没有循环, 循环中的每个程序都报告错误
+    while let Some(cycle) = graph::find_cycle(&graph) {
+        let path = cycle.join(" -> ");
+        for pair in cycle.windows(2) {
+            let relation = &relations[&pair[0]];
+            let offset = relation
+                .after
+                .iter()
+                .chain(&relation.requires)
+                .find(|depend| depend.get_ref() == &pair[1])
+                .map(|depend| depend.span().start)
+                .unwrap_or(0);
+            report(
+                &pair[0],
+                offset,
+                "error",
+                format!("检测到循环依赖: {}", path),
+            );
+        }
+        if let Some(depends) = graph.get_mut(&cycle[0]) {
+            depends.retain(|depend| depend != &cycle[1]);
+        }
+    }
+
+    for module_issues in issues.values_mut() {
+        module_issues.sort_by_key(|issue| (issue.line, issue.column));
+    }
+    issues
+}
+
+/// 检查命令是否能在 PATH 中找到
+fn in_search_path(command: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(command).is_file()))
+        .unwrap_or(false)
+}
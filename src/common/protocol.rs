@@ -0,0 +1,504 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-25
+ * File: protocol
+ */
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::common::module_constants;
+use crate::common::health::HealthState;
+use crate::common::supervisor::ProcessState;
+
+// 控制协议: 客户端连接 <root>/run/supervisord.sock 后发送一行 JSON 请求,
+// supervisord 返回一行 JSON 应答后关闭连接. 处理请求期间 supervisord 定期发送
+// pending 为 true 的进度消息, 客户端收到后继续等待应答. 请求与应答都带有 version 字段,
+// 版本不一致时 supervisord 返回错误而不执行请求, ex:
+// -> {"version":2,"command":"stop","names":["planning"],"groups":[],"all":false}
+// <- {"version":2,"pending":true,...}
+// <- {"version":2,"error":null,"results":[{"name":"planning","ok":true,"state":"stopped",...}],...}
+
+/// 控制协议的版本号, 请求或应答的格式不兼容时加 1
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// supervisord 控制套接字的文件名, 位于 <root>/run 下
+pub const SOCKET_NAME: &str = "supervisord.sock";
+
+/// 控制套接字的权限, 只允许所有者与同组用户连接
+pub const SOCKET_MODE: u32 = 0o660;
+
+/// 心跳套接字的文件名, 位于 <root>/run 下, 只接受 heartbeat 请求
+pub const HEARTBEAT_SOCKET_NAME: &str = "heartbeat.sock";
+
+/// 心跳套接字的权限, 程序可能以任意用户运行, 所有用户都可以连接,
+/// supervisord 再根据客户端的 uid 检查是否为对应程序的运行用户
+pub const HEARTBEAT_SOCKET_MODE: u32 = 0o666;
+
+/// 查询类请求等待应答的时间
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 启动、停止类请求等待下一条消息的时间. supervisord 会等待程序启动或停止完成后再应答,
+/// 总耗时由程序的 start_timeout 与 stop_grace_period 决定, 期间每隔 PROGRESS_INTERVAL 发送进度消息
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// supervisord 处理请求期间发送进度消息的间隔
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+
+/// supervisord 等待客户端发送请求的时间
+pub const SERVER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 获取 supervisord 控制套接字的路径
+pub fn socket_path(root: &Path) -> PathBuf {
+    root.join(module_constants::RUN_DIR_NAME).join(SOCKET_NAME)
+}
+
+/// 获取 supervisord 心跳套接字的路径
+pub fn heartbeat_socket_path(root: &Path) -> PathBuf {
+    root.join(module_constants::RUN_DIR_NAME)
+        .join(HEARTBEAT_SOCKET_NAME)
+}
+
+/// 带有协议版本号的消息
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    /// 协议版本号
+    version: u32,
+
+    /// 请求或应答
+    #[serde(flatten)]
+    body: T,
+}
+
+/// 客户端发送给 supervisord 的请求
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// 检查 supervisord 是否在运行
+    Ping {},
+
+    /// 启动程序
+    Start(Targets),
+
+    /// 停止程序
+    Stop(Targets),
+
+    /// 重启程序
+    Restart(Targets),
+
+    /// 列出所有程序的状态
+    List {},
+
+    /// 查看单个程序的状态与资源限制
+    Status { name: String },
+
+    /// 程序向 supervisord 报告自身存活, 用于 heartbeat 类型的健康检查
+    Heartbeat { name: String },
+}
+
+/// 请求作用的程序, 由 supervisord 解析为具体的程序列表
+#[derive(Serialize, Deserialize, Default)]
+pub struct Targets {
+    /// 程序名称或通配符, ex: planning, perception_*
+    #[serde(default)]
+    pub names: Vec<String>,
+
+    /// 分组名称
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// 是否作用于所有程序
+    #[serde(default)]
+    pub all: bool,
+}
+
+impl Request {
+    /// 客户端等待应答的时间
+    fn timeout(&self) -> Duration {
+        match self {
+            Request::Ping {}
+            | Request::List {}
+            | Request::Status { .. }
+            | Request::Heartbeat { .. } => QUERY_TIMEOUT,
+            _ => CONTROL_TIMEOUT,
+        }
+    }
+}
+
+/// supervisord 对请求的应答
+#[derive(Serialize, Deserialize, Default)]
+pub struct Response {
+    /// 为 true 时是处理请求期间的进度消息, 之后还会发送应答
+    #[serde(default)]
+    pub pending: bool,
+
+    /// 整个请求失败的原因, 为空表示请求已被处理
+    #[serde(default)]
+    pub error: Option<String>,
+
+    /// 每个程序的操作结果
+    #[serde(default)]
+    pub results: Vec<ActionResult>,
+
+    /// 程序状态列表
+    #[serde(default)]
+    pub programs: Vec<ProgramStatus>,
+
+    /// supervisord 自身的信息, 只在 ping 的应答中出现
+    #[serde(default)]
+    pub daemon: Option<DaemonInfo>,
+
+    /// 程序的资源限制, 只在 status 的应答中出现
+    #[serde(default)]
+    pub resources: Vec<ResourceStatus>,
+}
+
+/// 程序的一项资源限制
+#[derive(Serialize, Deserialize)]
+pub struct ResourceStatus {
+    /// 名称, 与 supervise.toml 中 [resources] 的字段相同, ex: cpus
+    pub name: String,
+
+    /// supervise.toml 中的配置值, 未配置时为空
+    pub configured: String,
+
+    /// 进程当前生效的值, 未运行时为空
+    pub effective: String,
+}
+
+/// supervisord 自身的信息
+#[derive(Serialize, Deserialize)]
+pub struct DaemonInfo {
+    /// supervisord 的进程号
+    pub pid: u32,
+
+    /// waytous 的版本号
+    pub version: String,
+
+    /// 控制协议的版本号
+    pub protocol: u32,
+
+    /// 已运行的时长(秒)
+    pub uptime: u64,
+
+    /// 受管程序的数量
+    pub programs: usize,
+}
+
+/// 对单个程序执行操作的结果
+#[derive(Serialize, Deserialize)]
+pub struct ActionResult {
+    /// 程序名称
+    pub name: String,
+
+    /// 操作是否成功
+    pub ok: bool,
+
+    /// 操作后的进程状态, 程序不存在时为空
+    #[serde(default)]
+    pub state: Option<ProcessState>,
+
+    /// 结果说明
+    pub message: String,
+}
+
+/// 单个程序的运行状态
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProgramStatus {
+    /// 程序名称
+    pub name: String,
+
+    /// 所属的分组
+    pub groups: Vec<String>,
+
+    /// 进程状态
+    pub state: ProcessState,
+
+    /// 进程号, 未运行时为空
+    pub pid: Option<u32>,
+
+    /// 本次运行的时长(秒)
+    pub uptime: u64,
+
+    /// 被自动重启的次数
+    pub restarts: u32,
+
+    /// 上一次运行的退出码, 被信号结束时为 128 + 信号值
+    pub exit_code: Option<i32>,
+
+    /// 最近一次状态变化的说明
+    pub message: String,
+
+    /// 健康状态, 没有配置健康检查或未运行时为 none
+    #[serde(default)]
+    pub health: HealthState,
+
+    /// 最近一次健康检查失败的原因
+    #[serde(default)]
+    pub health_message: String,
+
+    /// CPU 占用率, 100 表示占满一个核, 未运行时为空
+    #[serde(default)]
+    pub cpu_percent: Option<f64>,
+
+    /// 常驻内存(字节), 未运行时为空
+    #[serde(default)]
+    pub rss: Option<u64>,
+
+    /// 线程数, 未运行时为空
+    #[serde(default)]
+    pub threads: Option<u64>,
+
+    /// 打开的文件描述符数, 未运行时为空
+    #[serde(default)]
+    pub fds: Option<u64>,
+}
+
+/// 向 supervisord 发送请求并等待应答
+/// # Arguments
+/// * `root` - 安装根目录
+/// * `request` - 请求
+/// # Return
+/// * Ok(Response) - supervisord 的应答
+/// * Err(String) - 无法连接 supervisord、套接字不可信、等待超时或协议版本不一致
+pub fn request(root: &Path, request: &Request) -> Result<Response, String> {
+    // 心跳由程序自身发送, 程序的运行用户通常没有权限连接控制套接字
+    let path = match request {
+        Request::Heartbeat { .. } => heartbeat_socket_path(root),
+        _ => socket_path(root),
+    };
+    check_socket(&path)?;
+    let mut stream = UnixStream::connect(&path).map_err(|e| connect_error(&path, e))?;
+    let timeout = request.timeout();
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| format!("设置超时失败: {}", e))?;
+
+    write_message(
+        &mut stream,
+        &Envelope {
+            version: PROTOCOL_VERSION,
+            body: request,
+        },
+    )?;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let envelope: Envelope<Response> = read_message(&mut reader)
+            .map_err(|e| match e {
+                ReadError::Timeout => {
+                    format!("等待 supervisord 应答超时({} 秒)", timeout.as_secs())
+                }
+                ReadError::Other(err) => err,
+            })?
+            .ok_or("supervisord 未返回应答".to_string())?;
+        if envelope.version != PROTOCOL_VERSION {
+            return Err(format!(
+                "协议版本不一致: supervisord 为 {}, 当前为 {}, 请重启 supervisord",
+                envelope.version, PROTOCOL_VERSION
+            ));
+        }
+        // 进度消息说明 supervisord 仍在处理, 重新开始等待
+        if !envelope.body.pending {
+            return Ok(envelope.body);
+        }
+    }
+}
+
+/// 根据连接失败的原因给出提示
+fn connect_error(path: &Path, err: io::Error) -> String {
+    let hint = match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+            ", 请先执行 waytous supervisord daemon"
+        }
+        io::ErrorKind::PermissionDenied => ", 请使用 root 或控制套接字所属组的用户执行",
+        _ => "",
+    };
+    format!("无法连接 supervisord({}): {}{}", path.display(), err, hint)
+}
+
+/// 检查控制套接字是否可信, 只连接由 root 或当前用户创建的套接字
+fn check_socket(path: &Path) -> Result<(), String> {
+    let metadata = fs::symlink_metadata(path).map_err(|e| connect_error(path, e))?;
+    if !metadata.file_type().is_socket() {
+        return Err(format!("{} 不是套接字", path.display()));
+    }
+    // SAFETY: geteuid 没有参数且总是成功
+    let euid = unsafe { libc::geteuid() };
+    if metadata.uid() != 0 && metadata.uid() != euid {
+        return Err(format!(
+            "{} 的所有者(uid {})既不是 root 也不是当前用户, 拒绝连接",
+            path.display(),
+            metadata.uid()
+        ));
+    }
+    Ok(())
+}
+
+/// 获取连接到套接字的客户端进程的身份
+/// # Return
+/// * Ok(libc::ucred) - 客户端的 pid、uid 与 gid
+/// * Err(String) - 获取失败
+pub fn peer_credentials(stream: &UnixStream) -> Result<libc::ucred, String> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred 与 len 在调用期间有效, 且 len 与 cred 的大小一致
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(format!(
+            "获取客户端身份失败: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    Ok(cred)
+}
+
+/// 检查连接到控制套接字的客户端是否有权限, root、supervisord 的运行用户以及
+/// 主组或附加组为套接字所属组的用户可以发送请求
+/// # Arguments
+/// * `stream` - 客户端连接
+/// * `path` - 控制套接字路径
+/// # Return
+/// * Ok(()) - 有权限
+/// * Err(String) - 无法获取客户端身份或没有权限
+pub fn check_peer(stream: &UnixStream, path: &Path) -> Result<(), String> {
+    let cred = peer_credentials(stream)?;
+
+    // SAFETY: geteuid 没有参数且总是成功
+    let euid = unsafe { libc::geteuid() };
+    if cred.uid == 0 || cred.uid == euid {
+        return Ok(());
+    }
+    let socket_gid = fs::metadata(path)
+        .map(|m| m.gid())
+        .map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    if cred.gid == socket_gid || peer_groups(cred.pid).contains(&socket_gid) {
+        Ok(())
+    } else {
+        Err(format!("uid {} 没有权限控制 supervisord", cred.uid))
+    }
+}
+
+/// 从 /proc/<pid>/status 的 Groups 行读取客户端进程的附加组, 读取失败时为空
+fn peer_groups(pid: libc::pid_t) -> Vec<u32> {
+    fs::read_to_string(format!("/proc/{}/status", pid))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("Groups:"))
+                .map(|groups| {
+                    groups
+                        .split_whitespace()
+                        .filter_map(|gid| gid.parse().ok())
+                        .collect()
+                })
+        })
+        .unwrap_or_default()
+}
+
+/// supervisord 读取客户端的请求, 协议版本不一致时返回错误
+/// # Return
+/// * Ok(Some(Request)) - 请求
+/// * Ok(None) - 客户端已关闭连接
+/// * Err(String) - 读取超时、解析失败或协议版本不一致
+pub fn read_request(reader: &mut impl BufRead) -> Result<Option<Request>, String> {
+    let value: Value = match read_message(reader) {
+        Ok(Some(value)) => value,
+        Ok(None) => return Ok(None),
+        Err(ReadError::Timeout) => return Err("等待请求超时".to_string()),
+        Err(ReadError::Other(err)) => return Err(err),
+    };
+    let version = value.get("version").and_then(Value::as_u64);
+    if version != Some(PROTOCOL_VERSION as u64) {
+        return Err(format!(
+            "协议版本不一致: 请求为 {}, supervisord 为 {}",
+            version.map(|v| v.to_string()).unwrap_or("未知".to_string()),
+            PROTOCOL_VERSION
+        ));
+    }
+    serde_json::from_value(value)
+        .map(|envelope: Envelope<Request>| Some(envelope.body))
+        .map_err(|e| format!("不支持的请求: {}", e))
+}
+
+/// supervisord 发送应答
+pub fn write_response(writer: &mut impl Write, response: &Response) -> Result<(), String> {
+    write_message(
+        writer,
+        &Envelope {
+            version: PROTOCOL_VERSION,
+            body: response,
+        },
+    )
+}
+
+/// supervisord 发送进度消息, 告知客户端请求仍在处理
+pub fn write_progress(writer: &mut impl Write) -> Result<(), String> {
+    write_response(
+        writer,
+        &Response {
+            pending: true,
+            ..Default::default()
+        },
+    )
+}
+
+/// 读取消息失败的原因
+enum ReadError {
+    /// 等待超时
+    Timeout,
+
+    /// 读取或解析失败
+    Other(String),
+}
+
+/// 读取一行 JSON 消息
+/// # Return
+/// * Ok(Some(T)) - 消息
+/// * Ok(None) - 对端已关闭连接
+/// * Err(ReadError) - 读取超时、读取失败或解析失败
+fn read_message<T: for<'de> Deserialize<'de>>(
+    reader: &mut impl BufRead,
+) -> Result<Option<T>, ReadError> {
+    let mut line = String::new();
+    let size = reader.read_line(&mut line).map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ReadError::Timeout,
+        _ => ReadError::Other(format!("读取消息失败: {}", e)),
+    })?;
+    if size == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|e| ReadError::Other(format!("解析消息失败: {}", e)))
+}
+
+/// 写入一行 JSON 消息
+fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<(), String> {
+    let mut line = serde_json::to_string(message).map_err(|e| format!("序列化消息失败: {}", e))?;
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .map_err(|e| format!("发送消息失败: {}", e))
+}
@@ -0,0 +1,587 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-27
+ * File: resources
+ */
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+/// cgroup v2 的挂载点
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// supervisord 在自身 cgroup 下为受管程序创建的 cgroup 的父目录名称, 每个程序一个子 cgroup
+const CGROUP_NAME: &str = "waytous";
+
+/// supervisord 自身移入的子 cgroup 的名称, cgroup v2 中有进程的 cgroup 不能为子 cgroup 启用控制器
+const DAEMON_CGROUP_NAME: &str = "supervisord";
+
+/// 每 MB 的字节数
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// 调度策略
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheduler {
+    /// 普通分时调度, 默认策略
+    Other,
+
+    /// 批处理, 适合不需要交互的计算任务
+    Batch,
+
+    /// 极低优先级, 只在 CPU 空闲时运行
+    Idle,
+
+    /// 实时先进先出, 需要 priority
+    Fifo,
+
+    /// 实时时间片轮转, 需要 priority
+    Rr,
+}
+
+impl Scheduler {
+    fn policy(self) -> libc::c_int {
+        match self {
+            Scheduler::Other => libc::SCHED_OTHER,
+            Scheduler::Batch => libc::SCHED_BATCH,
+            Scheduler::Idle => libc::SCHED_IDLE,
+            Scheduler::Fifo => libc::SCHED_FIFO,
+            Scheduler::Rr => libc::SCHED_RR,
+        }
+    }
+
+    /// 是否为实时调度策略
+    pub fn is_realtime(self) -> bool {
+        matches!(self, Scheduler::Fifo | Scheduler::Rr)
+    }
+
+    fn name(policy: libc::c_int) -> String {
+        match policy {
+            libc::SCHED_OTHER => "other".to_string(),
+            libc::SCHED_BATCH => "batch".to_string(),
+            libc::SCHED_IDLE => "idle".to_string(),
+            libc::SCHED_FIFO => "fifo".to_string(),
+            libc::SCHED_RR => "rr".to_string(),
+            policy => policy.to_string(),
+        }
+    }
+}
+
+/// 程序的资源限制, 对应 supervise.toml 中的 [resources], 在启动程序时设置
+#[derive(Deserialize, Clone, PartialEq, Default)]
+pub struct Resources {
+    /// 允许运行的 CPU 编号, 为空时不限制, ex: [2, 3]
+    #[serde(default)]
+    pub cpus: Vec<usize>,
+
+    /// nice 值, -20 到 19, 越小优先级越高
+    #[serde(default)]
+    pub nice: Option<i32>,
+
+    /// 调度策略
+    #[serde(default)]
+    pub scheduler: Option<Scheduler>,
+
+    /// 实时调度的优先级, 1 到 99, 只在 scheduler 为 fifo 或 rr 时使用
+    #[serde(default)]
+    pub priority: Option<i32>,
+
+    /// 内存上限(MB), cgroup v2 可用时设置 memory.max, 否则设置 RLIMIT_AS
+    #[serde(default)]
+    pub memory_limit: Option<u64>,
+
+    /// 可以打开的文件数上限, 同时设置 RLIMIT_NOFILE 的软限制与硬限制
+    #[serde(default)]
+    pub nofile: Option<u64>,
+}
+
+impl Resources {
+    /// 检查配置中的取值
+    /// # Return
+    /// * Vec<String> - 不合法的取值说明, 为空表示正确
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(cpu) = self
+            .cpus
+            .iter()
+            .find(|cpu| **cpu >= libc::CPU_SETSIZE as usize)
+        {
+            errors.push(format!("CPU 编号 {} 超出范围", cpu));
+        }
+        if self.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+            errors.push("nice 必须在 -20 到 19 之间".to_string());
+        }
+        match (self.scheduler, self.priority) {
+            (Some(scheduler), None) if scheduler.is_realtime() => {
+                errors.push("实时调度策略需要设置 priority".to_string())
+            }
+            (Some(scheduler), Some(priority))
+                if scheduler.is_realtime() && !(1..=99).contains(&priority) =>
+            {
+                errors.push("priority 必须在 1 到 99 之间".to_string())
+            }
+            (Some(scheduler), Some(_)) if scheduler.is_realtime() => {}
+            (_, Some(_)) => errors.push("priority 只能与 fifo 或 rr 调度策略一起使用".to_string()),
+            _ => {}
+        }
+        if self.memory_limit == Some(0) {
+            errors.push("memory_limit 必须大于 0 MB".to_string());
+        }
+        if self
+            .memory_limit
+            .is_some_and(|mb| mb.checked_mul(MEGABYTE).is_none())
+        {
+            errors.push(format!("memory_limit 不能超过 {} MB", u64::MAX / MEGABYTE));
+        }
+        if self.nofile == Some(0) {
+            errors.push("nofile 必须大于 0".to_string());
+        }
+        errors
+    }
+
+    /// 配置的资源限制, 用于显示, 没有设置的项为空
+    /// # Return
+    /// * Vec<(&str, String)> - (名称, 取值)
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        let scheduler = self
+            .scheduler
+            .map(|scheduler| Scheduler::name(scheduler.policy()));
+        vec![
+            ("cpus", format_cpus(&self.cpus)),
+            ("nice", display(self.nice)),
+            ("scheduler", scheduler.unwrap_or_default()),
+            ("priority", display(self.priority)),
+            (
+                "memory_limit",
+                display(self.memory_limit.map(|mb| format!("{} MB", mb))),
+            ),
+            ("nofile", display(self.nofile)),
+        ]
+    }
+}
+
+fn display<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// 将 CPU 编号格式化为区间, ex: [0, 1, 2, 5] -> 0-2,5
+fn format_cpus(cpus: &[usize]) -> String {
+    let mut cpus = cpus.to_vec();
+    cpus.sort();
+    cpus.dedup();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cpu => *end = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// 启动子进程前在 supervisord 中准备好的设置, 在 fork 之后、exec 之前由子进程应用,
+/// 应用的过程中不分配内存, 只调用系统调用
+pub struct Launch {
+    /// 需要加入的 cgroup 的 cgroup.procs 路径
+    cgroup_procs: Option<CString>,
+
+    /// CPU 亲和性
+    cpus: Option<libc::cpu_set_t>,
+
+    nice: Option<i32>,
+    scheduler: Option<(libc::c_int, i32)>,
+
+    /// cgroup 不可用时使用 RLIMIT_AS 限制内存(字节)
+    address_space: Option<u64>,
+
+    nofile: Option<u64>,
+
+    /// 切换到的 (uid, gid)
+    user: Option<(u32, u32)>,
+}
+
+/// 设置失败时将失败的步骤写入 report 管道并返回错误, 只能在 Launch::apply 的 unsafe 块中使用
+macro_rules! fail {
+    ($report:expr, $message:literal) => {{
+        let error = io::Error::last_os_error();
+        libc::write(
+            $report,
+            $message.as_ptr() as *const libc::c_void,
+            $message.len(),
+        );
+        return Err(error);
+    }};
+}
+
+impl Launch {
+    /// 在 supervisord 中准备资源限制, 设置了内存上限时优先创建 cgroup
+    /// # Arguments
+    /// * `name` - 程序名称, 作为 cgroup 的名称
+    /// * `resources` - 资源限制
+    /// * `user` - 切换到的 (uid, gid), 在资源限制设置完成后切换, 以便以 root 权限设置
+    /// # Return
+    /// * (Launch, Option<String>) - 准备好的设置, 以及 cgroup 不可用而改用 RLIMIT_AS 的原因
+    pub fn prepare(
+        name: &str,
+        resources: &Resources,
+        user: Option<(u32, u32)>,
+    ) -> (Launch, Option<String>) {
+        let cpus = (!resources.cpus.is_empty()).then(|| {
+            // SAFETY: cpu_set_t 是普通的位图, 全 0 为合法值, CPU 编号已校验不超过 CPU_SETSIZE
+            unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                for cpu in &resources.cpus {
+                    libc::CPU_SET(*cpu, &mut set);
+                }
+                set
+            }
+        });
+        let scheduler = resources
+            .scheduler
+            .map(|scheduler| (scheduler.policy(), resources.priority.unwrap_or(0)));
+
+        let mut cgroup_procs = None;
+        let mut address_space = None;
+        let mut fallback = None;
+        if let Some(mb) = resources.memory_limit {
+            let bytes = mb.saturating_mul(MEGABYTE);
+            match prepare_cgroup(name, bytes) {
+                Ok(procs) => {
+                    cgroup_procs = CString::new(procs.as_os_str().as_bytes()).ok();
+                }
+                Err(err) => {
+                    address_space = Some(bytes);
+                    fallback = Some(err);
+                }
+            }
+        }
+
+        let launch = Launch {
+            cgroup_procs,
+            cpus,
+            nice: resources.nice,
+            scheduler,
+            address_space,
+            nofile: resources.nofile,
+            user,
+        };
+        (launch, fallback)
+    }
+
+    /// 在子进程中应用设置, 只能在 fork 之后、exec 之前调用
+    /// # Arguments
+    /// * `report` - 管道的写端, 设置失败时写入失败的步骤
+    fn apply(&self, report: RawFd) -> io::Result<()> {
+        // SAFETY: 以下都是直接的系统调用, 参数指向的内存在调用期间有效, 失败时只写入静态字符串
+        unsafe {
+            if let Some(procs) = &self.cgroup_procs {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+                if fd < 0 || libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) != 1 {
+                    fail!(report, "加入 cgroup 失败");
+                }
+                libc::close(fd);
+            }
+            if let Some(cpus) = &self.cpus {
+                if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), cpus) != 0 {
+                    fail!(report, "设置 CPU 亲和性失败");
+                }
+            }
+            if let Some(nice) = self.nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    fail!(report, "设置 nice 失败");
+                }
+            }
+            if let Some((policy, priority)) = self.scheduler {
+                let param = libc::sched_param {
+                    sched_priority: priority,
+                };
+                if libc::sched_setscheduler(0, policy, &param) != 0 {
+                    fail!(report, "设置调度策略失败");
+                }
+            }
+            if let Some(bytes) = self.address_space {
+                let limit = libc::rlimit {
+                    rlim_cur: bytes,
+                    rlim_max: bytes,
+                };
+                if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                    fail!(report, "设置内存上限失败");
+                }
+            }
+            if let Some(nofile) = self.nofile {
+                let limit = libc::rlimit {
+                    rlim_cur: nofile,
+                    rlim_max: nofile,
+                };
+                if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
+                    fail!(report, "设置打开文件数上限失败");
+                }
+            }
+            if let Some((uid, gid)) = self.user {
+                if libc::getuid() == 0 && libc::setgroups(0, std::ptr::null()) != 0 {
+                    fail!(report, "清除附加组失败");
+                }
+                if libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+                    fail!(report, "切换用户失败");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 以准备好的设置启动子进程, 设置失败时错误信息中包含失败的步骤
+/// # Arguments
+/// * `command` - 子进程的命令
+/// * `launch` - 准备好的设置
+/// # Return
+/// * Ok(Child) - 子进程
+/// * Err(io::Error) - 启动失败
+pub fn spawn(command: &mut Command, launch: Launch) -> io::Result<Child> {
+    let mut fds = [0; 2];
+    // SAFETY: fds 有两个元素, pipe2 成功后写入两个新的文件描述符
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: 两个文件描述符由 pipe2 创建, 只在这里获得所有权
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    let report = writer.as_raw_fd();
+    // SAFETY: Launch::apply 只调用系统调用, 不分配内存
+    unsafe {
+        command.pre_exec(move || launch.apply(report));
+    }
+    let result = command.spawn();
+
+    // 关闭写端后, 子进程 exec 或退出时管道关闭, 读取不会阻塞
+    drop(writer);
+    result.map_err(|err| {
+        let mut step = String::new();
+        let _ = reader.read_to_string(&mut step);
+        if step.is_empty() {
+            err
+        } else {
+            io::Error::new(err.kind(), format!("{}: {}", step, err))
+        }
+    })
+}
+
+/// 获取 supervisord 启动时所在的 cgroup 目录, 第一次调用时从 /proc/self/cgroup 读取并缓存,
+/// supervisord 移入子 cgroup 后仍返回原来的目录
+/// # Return
+/// * Option<&Path> - cgroup 目录, ex: /sys/fs/cgroup/system.slice/waytous.service,
+///   cgroup v2 不可用时为空
+fn delegated_cgroup() -> Option<&'static Path> {
+    static DELEGATED: OnceLock<Option<PathBuf>> = OnceLock::new();
+    DELEGATED
+        .get_or_init(|| {
+            let cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+            let path = cgroup.lines().find_map(|line| line.strip_prefix("0::"))?;
+            let dir = Path::new(CGROUP_ROOT).join(path.trim().trim_start_matches('/'));
+            dir.join("cgroup.controllers").exists().then_some(dir)
+        })
+        .as_deref()
+}
+
+/// 获取程序的 cgroup 目录, ex: /sys/fs/cgroup/system.slice/waytous.service/waytous/planning
+fn cgroup_path(name: &str) -> Option<PathBuf> {
+    delegated_cgroup().map(|dir| dir.join(CGROUP_NAME).join(name))
+}
+
+/// supervisord 启动时调用, 将自身移入所在 cgroup 的子 cgroup,
+/// 使所在的 cgroup 可以为程序的 cgroup 启用 memory 控制器, 失败时忽略,
+/// 设置内存上限时由 prepare_cgroup 报告原因
+pub fn init_cgroup() {
+    // 根 cgroup 不受"有进程的 cgroup 不能启用控制器"的限制
+    let Some(dir) = delegated_cgroup().filter(|dir| *dir != Path::new(CGROUP_ROOT)) else {
+        return;
+    };
+    let leaf = dir.join(DAEMON_CGROUP_NAME);
+    if fs::create_dir_all(&leaf).is_ok() {
+        let _ = fs::write(leaf.join("cgroup.procs"), std::process::id().to_string());
+    }
+}
+
+/// 在 supervisord 所在的 cgroup 下为程序创建 cgroup 并设置 memory.max,
+/// 不会修改 supervisord 所在 cgroup 之外的 cgroup
+/// # Return
+/// * Ok(PathBuf) - cgroup.procs 的路径
+/// * Err(String) - cgroup v2 不可用、没有为 supervisord 委派 cgroup 或没有权限
+fn prepare_cgroup(name: &str, bytes: u64) -> Result<PathBuf, String> {
+    let delegated = delegated_cgroup().ok_or("cgroup v2 不可用")?;
+    let controllers = fs::read_to_string(delegated.join("cgroup.controllers"))
+        .map_err(|_| "cgroup v2 不可用".to_string())?;
+    if !controllers.split_whitespace().any(|c| c == "memory") {
+        return Err(format!(
+            "{} 没有可用的 memory 控制器, 需要为 supervisord 委派 cgroup (ex: systemd 的 Delegate=yes)",
+            delegated.display()
+        ));
+    }
+    let write = |path: PathBuf, value: &str| {
+        fs::write(&path, value).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))
+    };
+    let base = delegated.join(CGROUP_NAME);
+    fs::create_dir_all(&base).map_err(|e| format!("创建 {} 失败: {}", base.display(), e))?;
+    for dir in [delegated, base.as_path()] {
+        let enabled = fs::read_to_string(dir.join("cgroup.subtree_control")).unwrap_or_default();
+        if !enabled.split_whitespace().any(|c| c == "memory") {
+            write(dir.join("cgroup.subtree_control"), "+memory")?;
+        }
+    }
+    let dir = base.join(name);
+    fs::create_dir_all(&dir).map_err(|e| format!("创建 {} 失败: {}", dir.display(), e))?;
+    write(dir.join("memory.max"), &bytes.to_string())?;
+    Ok(dir.join("cgroup.procs"))
+}
+
+/// 程序退出后删除其 cgroup, cgroup 中还有进程或不存在时忽略
+pub fn remove_cgroup(name: &str) {
+    if let Some(dir) = cgroup_path(name) {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+/// 读取进程当前生效的资源限制, 与 Resources::describe 的名称一一对应
+/// # Arguments
+/// * `pid` - 进程号
+/// # Return
+/// * Vec<(&str, String)> - (名称, 取值), 读取失败的项为空
+pub fn effective(pid: u32) -> Vec<(&'static str, String)> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+
+    // SAFETY: cpu_set_t 是普通的位图, 全 0 为合法值, sched_getaffinity 只写入 set
+    let cpus = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        let size = std::mem::size_of::<libc::cpu_set_t>();
+        if libc::sched_getaffinity(pid as libc::pid_t, size, &mut set) == 0 {
+            let cpus: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+                .filter(|cpu| libc::CPU_ISSET(*cpu, &set))
+                .collect();
+            format_cpus(&cpus)
+        } else {
+            String::new()
+        }
+    };
+
+    let fields = stat_fields(pid);
+    let field = |index: usize| fields.get(index - 3).cloned().unwrap_or_default();
+    let policy = field(41).parse::<libc::c_int>().ok();
+    let priority = if policy.is_some_and(|p| p == libc::SCHED_FIFO || p == libc::SCHED_RR) {
+        field(40)
+    } else {
+        String::new()
+    };
+
+    let limits = fs::read_to_string(proc_dir.join("limits")).unwrap_or_default();
+    let limit = |name: &str| {
+        limits
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line[name.len()..].split_whitespace().next())
+            .map(str::to_string)
+            .unwrap_or_default()
+    };
+
+    // 进程在 supervisord 创建的 cgroup 中时显示 memory.max, 否则显示 RLIMIT_AS
+    let cgroup = fs::read_to_string(proc_dir.join("cgroup")).unwrap_or_default();
+    let base = delegated_cgroup().map(|dir| dir.join(CGROUP_NAME));
+    let memory = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| Path::new(CGROUP_ROOT).join(path.trim().trim_start_matches('/')))
+        .filter(|dir| base.as_ref().is_some_and(|base| dir.starts_with(base)))
+        .and_then(|dir| fs::read_to_string(dir.join("memory.max")).ok())
+        .map(|max| format!("{} (cgroup)", format_bytes(max.trim())))
+        .unwrap_or_else(|| format!("{} (rlimit)", format_bytes(&limit("Max address space"))));
+
+    vec![
+        ("cpus", cpus),
+        ("nice", field(19)),
+        ("scheduler", policy.map(Scheduler::name).unwrap_or_default()),
+        ("priority", priority),
+        ("memory_limit", memory),
+        ("nofile", limit("Max open files")),
+    ]
+}
+
+/// 将字节数格式化为 MB, unlimited 与 max 原样显示
+fn format_bytes(bytes: &str) -> String {
+    match bytes.parse::<u64>() {
+        Ok(bytes) => format!("{} MB", bytes / 1024 / 1024),
+        Err(_) => bytes.to_string(),
+    }
+}
+
+/// 读取 /proc/<pid>/stat 中进程名之后的字段, 第 1 个为 state(总第 3 个字段),
+/// 进程名中可能包含空格与括号, 因此从最后一个 ')' 之后开始拆分
+fn stat_fields(pid: u32) -> Vec<String> {
+    fs::read_to_string(format!("/proc/{}/stat", pid))
+        .ok()
+        .and_then(|stat| {
+            stat.rsplit_once(')')
+                .map(|(_, rest)| rest.split_whitespace().map(str::to_string).collect())
+        })
+        .unwrap_or_default()
+}
+
+/// 进程当前的资源占用
+#[derive(Clone, Copy)]
+pub struct Usage {
+    /// 累计使用的 CPU 时间(用户态与内核态之和), 单位为 clock tick
+    pub cpu_ticks: u64,
+
+    /// 常驻内存(字节)
+    pub rss: u64,
+
+    /// 线程数
+    pub threads: u64,
+
+    /// 打开的文件描述符数
+    pub fds: u64,
+}
+
+/// 从 /proc 读取进程当前的资源占用
+/// # Arguments
+/// * `pid` - 进程号
+/// # Return
+/// * Some(Usage) - 资源占用
+/// * None - 进程不存在或已退出
+pub fn usage(pid: u32) -> Option<Usage> {
+    let fields = stat_fields(pid);
+    let field = |index: usize| fields.get(index - 3)?.parse::<u64>().ok();
+    // SAFETY: sysconf 只读取系统配置
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+    let fds = fs::read_dir(format!("/proc/{}/fd", pid))
+        .map(|entries| entries.count() as u64)
+        .unwrap_or_default();
+    Some(Usage {
+        cpu_ticks: field(14)? + field(15)?,
+        rss: field(24)? * page_size,
+        threads: field(20)?,
+        fds,
+    })
+}
+
+/// 每秒的 clock tick 数, 用于将 Usage::cpu_ticks 换算为秒
+pub fn clock_ticks() -> u64 {
+    // SAFETY: sysconf 只读取系统配置
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64
+}
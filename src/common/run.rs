@@ -0,0 +1,220 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-15
+ * File: run
+ */
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::Builder;
+
+use crate::common::common::{module_constants, VersionMetaData};
+use crate::common::deb::debian_architecture;
+use crate::common::package::{
+    append_directory, append_file, append_symlink, collect_package_files, io_error, now,
+    sha256_hex, shell_quote, PackageFileKind, MAINTAINER_SCRIPTS,
+};
+
+/// 自解压安装包的脚本模板, 脚本之后紧跟 tar.gz 格式的数据
+/// 数据中包含 module/(模块文件) scripts/(维护脚本) checksums.sha256(模块文件校验值)
+const INSTALLER_TEMPLATE: &str = r#"#!/bin/sh
+# waytous 模块自解压安装包
+# 用法: ./@FILE_NAME@ [install|uninstall|verify|info]
+set -e
+
+MODULE_NAME=@MODULE_NAME@
+MODULE_VERSION=@MODULE_VERSION@
+# 安装根目录可以通过 WAYTOUS_ROOT 环境变量指定
+MODULE_DIR="${WAYTOUS_ROOT:-@DEFAULT_ROOT@}/@MODULES_DIR_NAME@/$MODULE_NAME"
+TARGET_DIR="$MODULE_DIR/$MODULE_VERSION"
+PAYLOAD_SHA256="@PAYLOAD_SHA256@"
+PAYLOAD_LINE=@PAYLOAD_LINE@
+
+payload() {
+    tail -n +"$PAYLOAD_LINE" "$0"
+}
+
+# 解压安装包到临时目录, 解压前校验整个安装包, 解压后校验每个模块文件
+extract() {
+    actual=$(payload | sha256sum | cut -d ' ' -f 1)
+    if [ "$actual" != "$PAYLOAD_SHA256" ]; then
+        echo "安装包校验失败: 期望 $PAYLOAD_SHA256, 实际 $actual" >&2
+        exit 1
+    fi
+
+    WORK_DIR=$(mktemp -d)
+    trap 'rm -rf "$WORK_DIR"' EXIT
+    payload | tar -xzf - -C "$WORK_DIR"
+    if ! (cd "$WORK_DIR/module" && sha256sum -c --quiet ../checksums.sha256); then
+        echo "解压后的模块文件校验失败" >&2
+        exit 1
+    fi
+}
+
+run_script() {
+    if [ -x "$WORK_DIR/scripts/$1" ]; then
+        "$WORK_DIR/scripts/$1" "$2"
+    fi
+}
+
+# 安装到 <name>/<version> 并将 current 指向该版本, 其他版本保留以便切换
+do_install() {
+    if [ -f "$MODULE_DIR/version.toml" ]; then
+        echo "$MODULE_DIR 为旧的单版本布局, 请先执行 waytous module remove --name $MODULE_NAME" >&2
+        exit 1
+    fi
+    extract
+    run_script preinst install
+    rm -rf "$TARGET_DIR"
+    mkdir -p "$TARGET_DIR"
+    cp -a "$WORK_DIR/module/." "$TARGET_DIR/"
+    ln -sfn "$MODULE_VERSION" "$MODULE_DIR/current"
+    run_script postinst configure
+    echo "$MODULE_NAME $MODULE_VERSION 已安装到 $TARGET_DIR"
+}
+
+# 只卸载当前安装包对应的版本, current 指向该版本时一并删除
+do_uninstall() {
+    extract
+    run_script prerm remove
+    rm -rf "$TARGET_DIR"
+    if [ "$(readlink "$MODULE_DIR/current" 2>/dev/null)" = "$MODULE_VERSION" ]; then
+        rm -f "$MODULE_DIR/current"
+    fi
+    rmdir "$MODULE_DIR" 2>/dev/null || true
+    run_script postrm remove
+    echo "$MODULE_NAME $MODULE_VERSION 已卸载"
+}
+
+do_verify() {
+    extract
+    echo "安装包校验通过"
+    if [ -d "$TARGET_DIR" ]; then
+        if (cd "$TARGET_DIR" && sha256sum -c --quiet "$WORK_DIR/checksums.sha256"); then
+            echo "已安装的 $MODULE_NAME 与安装包一致"
+        else
+            echo "已安装的 $MODULE_NAME 与安装包不一致" >&2
+            exit 1
+        fi
+    fi
+}
+
+case "${1:-install}" in
+    install) do_install ;;
+    uninstall) do_uninstall ;;
+    verify) do_verify ;;
+    info) echo "$MODULE_NAME $MODULE_VERSION -> $TARGET_DIR" ;;
+    *)
+        echo "用法: $0 [install|uninstall|verify|info]" >&2
+        exit 1
+        ;;
+esac
+exit 0
+"#;
+
+/// 将模块打包为自解压安装包, 安装位置为 <root>/modules/<name>/<version>,
+/// 其中 root 在安装时由 WAYTOUS_ROOT 环境变量指定, 默认为 /opt/waytous
+/// # Arguments
+/// * `metadata` - 模块元数据, 会以 version.toml 写入安装目录
+/// * `install_dir` - 模块的安装目录树
+/// * `scripts_dir` - 维护脚本所在目录, 目录中的 preinst/postinst/prerm/postrm 会被打包
+/// * `output_dir` - 安装包的输出目录
+/// # Return
+/// * Ok(PathBuf) - 生成的安装包路径
+/// * Err(String) - 打包失败的原因
+pub fn write_run(
+    metadata: &VersionMetaData,
+    install_dir: &Path,
+    scripts_dir: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf, String> {
+    let files = collect_package_files(metadata, install_dir)?;
+
+    // 生成数据部分, 同时记录模块文件的校验值
+    let mut checksums = String::new();
+    let mut payload = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    append_directory(&mut payload, "module", 0o755, now()).map_err(io_error)?;
+    for file in &files {
+        let path = format!("module/{}", file.relative_path);
+        match &file.kind {
+            PackageFileKind::Directory => {
+                append_directory(&mut payload, &path, file.mode, file.mtime).map_err(io_error)?
+            }
+            PackageFileKind::File(contents) => {
+                append_file(&mut payload, &path, file.mode, file.mtime, contents)
+                    .map_err(io_error)?;
+                checksums.push_str(&format!(
+                    "{}  ./{}\n",
+                    sha256_hex(contents),
+                    file.relative_path
+                ));
+            }
+            PackageFileKind::Symlink(link) => {
+                append_symlink(&mut payload, &path, link, file.mtime).map_err(io_error)?
+            }
+        }
+    }
+    append_file(
+        &mut payload,
+        "checksums.sha256",
+        0o644,
+        now(),
+        checksums.as_bytes(),
+    )
+    .map_err(io_error)?;
+    append_directory(&mut payload, "scripts", 0o755, now()).map_err(io_error)?;
+    for script in MAINTAINER_SCRIPTS {
+        let script_path = scripts_dir.join(script);
+        if script_path.is_file() {
+            let contents = fs::read(&script_path)
+                .map_err(|e| format!("读取 {} 失败: {}", script_path.display(), e))?;
+            append_file(
+                &mut payload,
+                &format!("scripts/{}", script),
+                0o755,
+                now(),
+                &contents,
+            )
+            .map_err(io_error)?;
+        }
+    }
+    let payload = payload
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(io_error)?;
+
+    // 生成安装脚本, 数据部分从脚本的下一行开始
+    let architecture = debian_architecture(&metadata.platform);
+    if !architecture
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(format!("平台 {:?} 无效", metadata.platform));
+    }
+    let file_name = format!(
+        "{}-{}-{}.run",
+        metadata.name, metadata.version, architecture
+    );
+    let script = INSTALLER_TEMPLATE
+        .replace("@FILE_NAME@", &file_name)
+        .replace("@MODULE_NAME@", &shell_quote(&metadata.name))
+        .replace("@MODULE_VERSION@", &shell_quote(&metadata.version))
+        .replace("@DEFAULT_ROOT@", module_constants::DEFAULT_ROOT)
+        .replace("@MODULES_DIR_NAME@", module_constants::MODULES_DIR_NAME)
+        .replace("@PAYLOAD_SHA256@", &sha256_hex(&payload));
+    let script = script.replace("@PAYLOAD_LINE@", &(script.lines().count() + 1).to_string());
+
+    let run_path = output_dir.join(file_name);
+    let mut run = fs::File::create(&run_path)
+        .map_err(|e| format!("创建 {} 失败: {}", run_path.display(), e))?;
+    run.write_all(script.as_bytes()).map_err(io_error)?;
+    run.write_all(&payload).map_err(io_error)?;
+    fs::set_permissions(&run_path, fs::Permissions::from_mode(0o755)).map_err(io_error)?;
+
+    Ok(run_path)
+}
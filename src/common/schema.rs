@@ -0,0 +1,318 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-24
+ * File: schema
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::common::common::VersionMetaData;
+use crate::common::depends::{self, Dependency};
+use crate::common::version;
+
+/// version.toml 当前的格式版本号
+/// 1: name/version/platform/author/description
+/// 2: 新增 depends/system_depends/conflicts
+/// 3: 新增 license/maintainers/homepage/repository/tags/git_commit/build_time
+pub const CURRENT_SCHEMA_VERSION: u32 = 3;
+
+/// 未声明 schema_version 的 version.toml 视为格式 1
+pub fn legacy_schema_version() -> u32 {
+    1
+}
+
+/// 格式升级步骤, (升级后的格式版本号, 升级说明, 升级函数)
+/// 新增字段时在此追加一步, 升级函数只补充或转换字段, 不认识的字段保存在 extra 中原样写回
+type Migration = (u32, &'static str, fn(&mut VersionMetaData));
+
+const MIGRATIONS: [Migration; 2] = [
+    (2, "新增 depends/system_depends/conflicts 字段", |_| {}),
+    (
+        3,
+        "新增 license/maintainers/homepage/repository/tags/git_commit/build_time 字段",
+        |_| {},
+    ),
+];
+
+/// version.toml 中的一个问题
+#[derive(Serialize)]
+pub struct SchemaIssue {
+    /// 问题级别, error/warning
+    pub level: &'static str,
+
+    /// 行号, 从 1 开始
+    pub line: usize,
+
+    /// 列号, 从 1 开始
+    pub column: usize,
+
+    /// 问题描述
+    pub message: String,
+}
+
+/// 用于定位字段位置的元数据, 只包含需要校验内容的字段
+#[derive(Deserialize)]
+struct SpannedMetaData {
+    schema_version: Option<Spanned<u32>>,
+    name: Option<Spanned<String>>,
+    version: Option<Spanned<String>>,
+    depends: Option<Vec<Spanned<String>>>,
+    system_depends: Option<Vec<Spanned<String>>>,
+    conflicts: Option<Vec<Spanned<String>>>,
+    homepage: Option<Spanned<String>>,
+    repository: Option<Spanned<String>>,
+    build_time: Option<Spanned<String>>,
+}
+
+/// 所有已知的字段, 其余字段会被原样保留并给出提示
+const KNOWN_FIELDS: [&str; 16] = [
+    "schema_version",
+    "name",
+    "version",
+    "platform",
+    "author",
+    "description",
+    "depends",
+    "system_depends",
+    "conflicts",
+    "license",
+    "maintainers",
+    "homepage",
+    "repository",
+    "tags",
+    "git_commit",
+    "build_time",
+];
+
+/// 读取 version.toml 并将旧的格式升级到当前格式, 升级只在内存中进行
+/// # Arguments
+/// * `path` - 元数据文件路径
+/// # Return
+/// * Ok((VersionMetaData, Vec<String>)) - 升级后的元数据以及执行过的升级说明
+/// * Err(String) - 读取失败、解析失败或格式版本高于当前支持的版本
+pub fn load_version_meta_data(path: &Path) -> Result<(VersionMetaData, Vec<String>), String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    let mut metadata: VersionMetaData = toml::from_str(&contents).map_err(|e| {
+        let (line, column) = e
+            .span()
+            .map(|span| line_column(&contents, span.start))
+            .unwrap_or((1, 1));
+        format!(
+            "解析 {}:{}:{} 失败: {}",
+            path.display(),
+            line,
+            column,
+            e.message()
+        )
+    })?;
+    let notes = migrate(&mut metadata).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok((metadata, notes))
+}
+
+/// 将元数据升级到当前格式
+/// # Arguments
+/// * `metadata` - 模块元数据
+/// # Return
+/// * Ok(Vec<String>) - 执行过的升级说明, 为空表示已经是当前格式
+/// * Err(String) - 格式版本高于当前支持的版本
+pub fn migrate(metadata: &mut VersionMetaData) -> Result<Vec<String>, String> {
+    if metadata.schema_version > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "格式版本 {} 高于当前支持的 {}, 请升级 waytous",
+            metadata.schema_version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    let mut notes = Vec::new();
+    for (target, description, step) in MIGRATIONS {
+        if metadata.schema_version < target {
+            step(metadata);
+            notes.push(format!(
+                "格式 {} -> {}: {}",
+                metadata.schema_version, target, description
+            ));
+            metadata.schema_version = target;
+        }
+    }
+    Ok(notes)
+}
+
+/// 校验 version.toml 的内容, 问题按出现的位置排序
+/// # Arguments
+/// * `contents` - version.toml 的内容
+/// # Return
+/// * Vec<SchemaIssue> - 发现的问题, 为空表示校验通过
+pub fn validate_version_meta_data(contents: &str) -> Vec<SchemaIssue> {
+    let mut issues = Vec::new();
+    let mut error = |offset: usize, message: String| {
+        let (line, column) = line_column(contents, offset);
+        issues.push(SchemaIssue {
+            level: "error",
+            line,
+            column,
+            message,
+        });
+    };
+
+    // 先按完整的结构解析, 语法错误与字段类型错误在这一步发现
+    if let Err(e) = toml::from_str::<VersionMetaData>(contents) {
+        error(
+            e.span().map(|span| span.start).unwrap_or(0),
+            e.message().to_string(),
+        );
+        return issues;
+    }
+    let Ok(spanned) = toml::from_str::<SpannedMetaData>(contents) else {
+        return issues;
+    };
+
+    match &spanned.schema_version {
+        Some(schema_version) if *schema_version.get_ref() > CURRENT_SCHEMA_VERSION => error(
+            schema_version.span().start,
+            format!(
+                "schema_version {} 高于当前支持的 {}",
+                schema_version.get_ref(),
+                CURRENT_SCHEMA_VERSION
+            ),
+        ),
+        Some(schema_version) if *schema_version.get_ref() == 0 => error(
+            schema_version.span().start,
+            "schema_version 从 1 开始".to_string(),
+        ),
+        _ => {}
+    }
+    match &spanned.name {
+        Some(name) if name.get_ref().trim().is_empty() => {
+            error(name.span().start, "name 不能为空".to_string())
+        }
+        Some(name) => {
+            if let Err(err) = validate_module_name(name.get_ref()) {
+                error(name.span().start, err);
+            }
+        }
+        None => error(0, "缺少字段 name".to_string()),
+    }
+    match &spanned.version {
+        Some(module_version) => {
+            if let Err(err) = version::parse_version(module_version.get_ref()) {
+                error(module_version.span().start, err);
+            }
+        }
+        None => error(0, "缺少字段 version".to_string()),
+    }
+    for specs in [&spanned.depends, &spanned.conflicts].into_iter().flatten() {
+        for spec in specs {
+            if let Err(err) = Dependency::parse(spec.get_ref()) {
+                error(spec.span().start, err);
+            }
+        }
+    }
+    for spec in spanned.system_depends.iter().flatten() {
+        if let Err(err) = depends::check_system_dependency(spec.get_ref()) {
+            error(spec.span().start, err);
+        }
+    }
+
+    for url in [&spanned.homepage, &spanned.repository]
+        .into_iter()
+        .flatten()
+    {
+        if let Err(err) = validate_url(url.get_ref()) {
+            error(url.span().start, err);
+        }
+    }
+    if let Some(build_time) = &spanned.build_time {
+        if !build_time.get_ref().is_empty()
+            && chrono::DateTime::parse_from_rfc3339(build_time.get_ref()).is_err()
+        {
+            error(
+                build_time.span().start,
+                format!(
+                    "build_time {} 不是 RFC 3339 格式的时间",
+                    build_time.get_ref()
+                ),
+            );
+        }
+    }
+
+    // 不认识的字段只给出提示, 读写时会原样保留
+    if let Ok(keys) = toml::from_str::<BTreeMap<Spanned<String>, toml::Value>>(contents) {
+        for key in keys.keys() {
+            if !KNOWN_FIELDS.contains(&key.get_ref().as_str()) {
+                let (line, column) = line_column(contents, key.span().start);
+                issues.push(SchemaIssue {
+                    level: "warning",
+                    line,
+                    column,
+                    message: format!("未知字段 {}, 将被原样保留", key.get_ref()),
+                });
+            }
+        }
+    }
+
+    issues.sort_by_key(|issue| (issue.line, issue.column));
+    issues
+}
+
+/// 检查模块名称, 模块名称会用于安装路径、软件包名称以及安装脚本,
+/// 只能包含字母、数字以及 _ - . +, 并以字母或数字开头
+/// # Arguments
+/// * `name` - 模块名称
+/// # Return
+/// * Ok(()) - 格式正确
+/// * Err(String) - 格式错误的原因
+pub fn validate_module_name(name: &str) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.+".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "模块名称 {:?} 无效, 只能包含字母、数字以及 _ - . +, 并以字母或数字开头",
+            name
+        ))
+    }
+}
+
+/// 检查项目主页或源码仓库地址的格式, 空字符串表示未设置
+/// # Arguments
+/// * `url` - 地址, 支持 http(s)://, ssh://, git:// 以及 git@host:path
+/// # Return
+/// * Ok(()) - 格式正确
+/// * Err(String) - 格式错误的原因
+pub fn validate_url(url: &str) -> Result<(), String> {
+    const SCHEMES: [&str; 4] = ["http://", "https://", "ssh://", "git://"];
+    let valid = url.is_empty()
+        || SCHEMES.iter().any(|scheme| {
+            url.strip_prefix(scheme)
+                .is_some_and(|rest| !rest.is_empty())
+        })
+        || url
+            .strip_prefix("git@")
+            .is_some_and(|rest| rest.contains(':'));
+    if valid && !url.contains(char::is_whitespace) {
+        Ok(())
+    } else {
+        Err(format!(
+            "地址 {} 格式错误, ex: https://example.com/foo, git@example.com:foo.git",
+            url
+        ))
+    }
+}
+
+/// 将字节偏移转换为行号与列号, 均从 1 开始, 列号按字符计算
+pub fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(contents.len());
+    let before = &contents[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
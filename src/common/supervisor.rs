@@ -0,0 +1,1631 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-25
+ * File: supervisor
+ */
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::BufReader;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::common;
+use crate::common::graph;
+use crate::common::health::{self, HealthCheck, HealthState, Probe};
+use crate::common::logfile;
+use crate::common::program::{self, Program, RestartPolicy};
+use crate::common::protocol::{
+    self, ActionResult, DaemonInfo, ProgramStatus, Request, ResourceStatus, Response, Targets,
+};
+use crate::common::resources::{self, Launch, Usage};
+use crate::common::schema;
+use crate::{print_error_msg, print_info_msg, print_warning_msg};
+
+/// 检查进程状态的间隔
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 从 /proc 读取资源占用的间隔, CPU 占用率为两次读取之间的平均值
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 收到 SIGTERM/SIGINT 后置位, supervisord 停止所有程序后退出
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// 受管程序的进程状态
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessState {
+    /// 未运行, 初始状态或被手动停止
+    Stopped,
+
+    /// 已启动, 尚未持续运行到 start_timeout 或尚未通过就绪检查
+    Starting,
+
+    /// 正常运行中
+    Running,
+
+    /// 启动失败或意外退出, 等待重启
+    Backoff,
+
+    /// 已发送停止信号, 等待进程退出
+    Stopping,
+
+    /// 运行中退出, 按重启策略不再重启
+    Exited,
+
+    /// 短时间内频繁失败, 不再自动重启, 需要使用 restart 手动重启
+    Fatal,
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ProcessState::Stopped => "stopped",
+            ProcessState::Starting => "starting",
+            ProcessState::Running => "running",
+            ProcessState::Backoff => "backoff",
+            ProcessState::Stopping => "stopping",
+            ProcessState::Exited => "exited",
+            ProcessState::Fatal => "fatal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 一个受管程序及其进程
+struct Process {
+    /// 程序定义
+    program: Program,
+
+    /// 日志文件路径
+    log_path: PathBuf,
+
+    /// 写入日志文件的 logger, 每次启动时按最新的定义重新创建
+    logger: Option<Arc<dyn log::Log>>,
+
+    /// 进程状态
+    state: ProcessState,
+
+    /// 正在运行的子进程
+    child: Option<Child>,
+
+    /// 本次启动的时刻
+    started: Option<Instant>,
+
+    /// 上一次运行的退出码
+    exit_code: Option<i32>,
+
+    /// 被自动重启的次数
+    restarts: u32,
+
+    /// 连续失败的次数, 决定下一次重启前的等待时间
+    retries: u32,
+
+    /// 时间窗口内每次失败的时刻, 用于判定频繁崩溃
+    crashes: VecDeque<Instant>,
+
+    /// backoff 状态下下一次启动的时刻
+    next_start: Option<Instant>,
+
+    /// stopping 状态下强制结束的时刻
+    stop_deadline: Option<Instant>,
+
+    /// 最近一次状态变化的说明
+    message: String,
+
+    /// 健康状态
+    health: HealthState,
+
+    /// 最近一次健康检查失败的原因
+    health_message: String,
+
+    /// 存活检查连续失败的次数
+    failures: u32,
+
+    /// 下一次健康检查的时刻
+    next_probe: Option<Instant>,
+
+    /// 正在执行的 exec 或 tcp 检查
+    probe: Option<JoinHandle<Result<(), String>>>,
+
+    /// 最近一次收到心跳的时刻, 启动时为启动的时刻
+    heartbeat: Option<Instant>,
+
+    /// 因存活检查失败而停止, 退出后需要重新启动
+    restart_pending: bool,
+
+    /// supervisord 主动结束进程的原因, 进程退出后作为状态说明
+    exit_reason: Option<String>,
+
+    /// 最近一次读取的资源占用
+    usage: Option<Usage>,
+
+    /// 最近一次读取资源占用的时刻, 启动时为启动的时刻
+    sampled: Option<Instant>,
+
+    /// 最近两次读取之间的 CPU 占用率
+    cpu_percent: Option<f64>,
+}
+
+impl Process {
+    fn new(program: Program, log_path: PathBuf) -> Self {
+        Process {
+            program,
+            log_path,
+            logger: None,
+            state: ProcessState::Stopped,
+            child: None,
+            started: None,
+            exit_code: None,
+            restarts: 0,
+            retries: 0,
+            crashes: VecDeque::new(),
+            next_start: None,
+            stop_deadline: None,
+            message: String::new(),
+            health: HealthState::None,
+            health_message: String::new(),
+            failures: 0,
+            next_probe: None,
+            probe: None,
+            heartbeat: None,
+            restart_pending: false,
+            exit_reason: None,
+            usage: None,
+            sampled: None,
+            cpu_percent: None,
+        }
+    }
+
+    /// 向程序的日志写入 supervisord 对该程序的操作
+    fn log_event(&self, message: &str) {
+        if let Some(logger) = &self.logger {
+            logfile::write_line(logger.as_ref(), "supervisord", message);
+        }
+    }
+
+    /// 启动子进程, 子进程位于独立的进程组中, 停止时向整个进程组发送信号,
+    /// 标准输出与标准错误写入程序的日志文件, 日志文件无法打开时丢弃,
+    /// 资源限制在 exec 之前设置, 之后再切换到程序定义的用户
+    fn spawn(&mut self) {
+        // 名称会用于拼接日志与 cgroup 的路径, 不合法时不再重启
+        if let Err(err) = schema::validate_module_name(&self.program.name) {
+            self.state = ProcessState::Fatal;
+            self.next_start = None;
+            self.message = err;
+            print_error_msg!("{}", self.message);
+            return;
+        }
+        self.logger = match logfile::logger(&self.log_path, &self.program.log) {
+            Ok(logger) => Some(logger),
+            Err(err) => {
+                print_warning_msg!("{} 的输出将被丢弃: {}", self.program.name, err);
+                None
+            }
+        };
+        let output = || match self.logger {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        };
+        let mut command = Command::new(self.program.command_path());
+        command
+            .args(&self.program.args)
+            .current_dir(self.program.working_dir())
+            .envs(&self.program.environment)
+            .stdin(Stdio::null())
+            .stdout(output())
+            .stderr(output())
+            .process_group(0);
+        let user = if self.program.user.is_empty() {
+            None
+        } else {
+            match common::user_ids(&self.program.user) {
+                Some(ids) => Some(ids),
+                None => {
+                    self.message = format!("用户 {} 不存在", self.program.user);
+                    self.schedule_restart();
+                    return;
+                }
+            }
+        };
+        let (launch, fallback) = Launch::prepare(&self.program.name, &self.program.resources, user);
+        if let Some(reason) = fallback {
+            self.log_event(&format!("{}, 使用 RLIMIT_AS 限制内存", reason));
+        }
+        match resources::spawn(&mut command, launch) {
+            Ok(mut child) => {
+                print_info_msg!("{} 已启动, pid {}", self.program.name, child.id());
+                if let Some(logger) = &self.logger {
+                    if let Some(stdout) = child.stdout.take() {
+                        logfile::capture(stdout, "stdout", logger.clone());
+                    }
+                    if let Some(stderr) = child.stderr.take() {
+                        logfile::capture(stderr, "stderr", logger.clone());
+                    }
+                }
+                self.log_event(&format!("已启动, pid {}", child.id()));
+                self.child = Some(child);
+                self.state = ProcessState::Starting;
+                self.started = Some(Instant::now());
+                self.next_start = None;
+                self.usage = None;
+                self.sampled = self.started;
+                self.cpu_percent = None;
+                self.reset_health();
+            }
+            Err(err) => {
+                self.message = format!("启动 {} 失败: {}", self.program.command, err);
+                self.log_event(&self.message);
+                self.schedule_restart();
+            }
+        }
+    }
+
+    /// 记录一次失败并安排重启, self.message 为失败的原因: 时间窗口内的失败次数达到上限时
+    /// 进入 fatal 状态并执行 hook, 否则进入 backoff 状态, 按指数退避等待后重启
+    fn schedule_restart(&mut self) {
+        let now = Instant::now();
+        let crash_loop = &self.program.crash_loop;
+        let window = Duration::from_secs(crash_loop.window);
+        self.crashes.push_back(now);
+        while self
+            .crashes
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > window)
+        {
+            self.crashes.pop_front();
+        }
+
+        if self.crashes.len() >= crash_loop.max_failures as usize {
+            self.state = ProcessState::Fatal;
+            self.next_start = None;
+            self.message = format!(
+                "{} 秒内失败 {} 次, 不再自动重启: {}",
+                crash_loop.window,
+                self.crashes.len(),
+                self.message
+            );
+            print_error_msg!("{} {}", self.program.name, self.message);
+            self.log_event(&self.message);
+            self.run_hook();
+            return;
+        }
+
+        let delay = self.backoff_delay();
+        self.retries += 1;
+        self.state = ProcessState::Backoff;
+        self.next_start = Some(now + delay);
+        print_warning_msg!(
+            "{} {}, {:.1} 秒后重启",
+            self.program.name,
+            self.message,
+            delay.as_secs_f64()
+        );
+    }
+
+    /// 下一次重启前的等待时间: initial * multiplier ^ 连续失败次数, 不超过 max, 再随机浮动 jitter 比例
+    fn backoff_delay(&self) -> Duration {
+        let backoff = &self.program.backoff;
+        let base = (backoff.initial * backoff.multiplier.powi(self.retries.min(64) as i32))
+            .min(backoff.max);
+        // 取 [-1, 1) 之间的随机数
+        let random =
+            RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64 * 2.0 - 1.0;
+        // 取值已在读取程序定义时校验, 这里再限制范围, 保证不会因为溢出或 NaN 而 panic
+        let seconds =
+            (base * (1.0 + backoff.jitter * random)).clamp(0.0, program::MAX_BACKOFF_SECONDS);
+        Duration::try_from_secs_f64(seconds)
+            .unwrap_or(Duration::from_secs_f64(program::Backoff::default().initial))
+    }
+
+    /// 判定为频繁崩溃后执行 hook, hook 的输出写入程序的日志
+    fn run_hook(&self) {
+        let Some(path) = self.program.hook_path() else {
+            return;
+        };
+        let mut command = Command::new(&path);
+        command
+            .args(&self.program.crash_loop.hook_args)
+            .current_dir(&self.program.module_dir)
+            .env("WAYTOUS_PROGRAM", &self.program.name)
+            .env("WAYTOUS_FAILURES", self.crashes.len().to_string())
+            .env("WAYTOUS_MESSAGE", &self.message)
+            .stdin(Stdio::null());
+        if self.logger.is_some() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        } else {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        match command.spawn() {
+            Ok(mut child) => {
+                self.log_event(&format!("执行 hook {}, pid {}", path.display(), child.id()));
+                if let Some(logger) = &self.logger {
+                    if let Some(stdout) = child.stdout.take() {
+                        logfile::capture(stdout, "hook", logger.clone());
+                    }
+                    if let Some(stderr) = child.stderr.take() {
+                        logfile::capture(stderr, "hook", logger.clone());
+                    }
+                }
+                // 在独立线程中回收 hook 进程, 不阻塞状态检查
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => {
+                print_error_msg!(
+                    "{} 执行 hook {} 失败: {}",
+                    self.program.name,
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// 进程启动后重置健康检查的状态, 配置了就绪检查时开始计划第一次检查
+    fn reset_health(&mut self) {
+        let checked = self.program.readiness.is_some() || self.program.liveness.is_some();
+        self.health = if checked {
+            HealthState::Pending
+        } else {
+            HealthState::None
+        };
+        self.health_message.clear();
+        self.failures = 0;
+        self.probe = None;
+        // 心跳从启动时开始计时, 程序启动后有 max_age 秒发送第一次心跳
+        self.heartbeat = Some(Instant::now());
+        self.restart_pending = false;
+        self.exit_reason = None;
+        self.next_probe = self
+            .program
+            .readiness
+            .as_ref()
+            .map(|check| Instant::now() + Duration::from_secs(check.initial_delay));
+    }
+
+    /// 进入 running 状态, 配置了存活检查时开始计划第一次检查
+    fn set_running(&mut self) {
+        self.state = ProcessState::Running;
+        self.next_probe = self
+            .program
+            .liveness
+            .as_ref()
+            .map(|check| Instant::now() + Duration::from_secs(check.initial_delay));
+    }
+
+    /// 当前状态下需要执行的健康检查, starting 状态为就绪检查, running 状态为存活检查
+    fn current_check(&self) -> Option<HealthCheck> {
+        match self.state {
+            ProcessState::Starting => self.program.readiness.clone(),
+            ProcessState::Running => self.program.liveness.clone(),
+            _ => None,
+        }
+    }
+
+    /// 执行一次健康检查, exec 与 tcp 检查在独立线程中执行, 避免阻塞状态检查
+    fn run_probe(&mut self, check: &HealthCheck) {
+        match &check.probe {
+            Probe::Heartbeat { max_age } => {
+                let result = health::check_heartbeat(self.heartbeat, *max_age);
+                self.on_probe(result);
+            }
+            Probe::File { .. } => {
+                let result = health::probe(&check.probe, &self.program, check.timeout);
+                self.on_probe(result);
+            }
+            probe => {
+                let probe = probe.clone();
+                let program = self.program.clone();
+                let timeout = check.timeout;
+                self.probe = Some(thread::spawn(move || {
+                    health::probe(&probe, &program, timeout)
+                }));
+            }
+        }
+    }
+
+    /// 根据健康检查的结果推进状态: 就绪检查成功后进入 running 状态,
+    /// 存活检查连续失败 failure_threshold 次后重启程序
+    fn on_probe(&mut self, result: Result<(), String>) {
+        match (self.state, result) {
+            (ProcessState::Starting, Ok(())) => {
+                print_info_msg!("{} 就绪检查通过", self.program.name);
+                self.health = HealthState::Healthy;
+                self.health_message.clear();
+                self.set_running();
+            }
+            (ProcessState::Starting, Err(err)) => {
+                self.health_message = err;
+            }
+            (ProcessState::Running, Ok(())) => {
+                if self.health == HealthState::Unhealthy {
+                    print_info_msg!("{} 存活检查恢复正常", self.program.name);
+                }
+                self.health = HealthState::Healthy;
+                self.health_message.clear();
+                self.failures = 0;
+            }
+            (ProcessState::Running, Err(err)) => {
+                let threshold = self
+                    .program
+                    .liveness
+                    .as_ref()
+                    .map(|check| check.failure_threshold)
+                    .unwrap_or(1);
+                self.failures += 1;
+                self.health = HealthState::Unhealthy;
+                print_warning_msg!(
+                    "{} 存活检查失败({}/{}): {}",
+                    self.program.name,
+                    self.failures,
+                    threshold,
+                    err
+                );
+                self.health_message = err;
+                if self.failures >= threshold {
+                    self.restart_unhealthy();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 存活检查连续失败, 按停止程序的方式结束进程, 进程退出后重新启动
+    fn restart_unhealthy(&mut self) {
+        print_warning_msg!(
+            "{} 存活检查连续 {} 次失败, 正在重启",
+            self.program.name,
+            self.failures
+        );
+        self.exit_reason = Some(format!(
+            "存活检查连续 {} 次失败: {}",
+            self.failures, self.health_message
+        ));
+        self.restart_pending = true;
+        self.probe = None;
+        self.next_probe = None;
+        self.signal(self.program.stop_signal_number());
+        self.state = ProcessState::Stopping;
+        self.stop_deadline =
+            Some(Instant::now() + Duration::from_secs(self.program.stop_grace_period));
+    }
+
+    /// 向子进程所在的进程组发送信号
+    fn signal(&self, signal: libc::c_int) {
+        if let Some(child) = &self.child {
+            // SAFETY: kill 只向指定的进程组发送信号, 不涉及内存访问
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), signal);
+            }
+        }
+    }
+
+    /// 检查子进程是否退出, 并推进状态
+    fn tick(&mut self) {
+        if let Some(child) = &mut self.child {
+            if let Ok(Some(status)) = child.try_wait() {
+                self.child = None;
+                self.exit_code = status.code().or_else(|| status.signal().map(|s| 128 + s));
+                self.log_event(&format!("进程退出, {}", status));
+                if self.program.resources.memory_limit.is_some() {
+                    resources::remove_cgroup(&self.program.name);
+                }
+                self.probe = None;
+                self.next_probe = None;
+                self.on_exit();
+            }
+        }
+        self.sample();
+
+        if let Some(probe) = self.probe.take_if(|probe| probe.is_finished()) {
+            let result = probe
+                .join()
+                .unwrap_or_else(|_| Err("健康检查线程异常退出".to_string()));
+            self.on_probe(result);
+        }
+        let now = Instant::now();
+        if let Some(check) = self.current_check() {
+            if self.probe.is_none() && self.next_probe.is_some_and(|t| t <= now) {
+                self.next_probe = Some(now + Duration::from_secs(check.interval));
+                self.run_probe(&check);
+            }
+        }
+
+        let started_for = |timeout: u64| {
+            self.started
+                .is_some_and(|t| t.elapsed() >= Duration::from_secs(timeout))
+        };
+        match self.state {
+            ProcessState::Starting
+                if self.program.readiness.is_some()
+                    && self.exit_reason.is_none()
+                    && started_for(self.program.start_timeout) =>
+            {
+                // 就绪检查超时, 结束进程后按启动失败处理
+                self.exit_reason = Some(format!(
+                    "就绪检查未在 {} 秒内通过: {}",
+                    self.program.start_timeout, self.health_message
+                ));
+                self.probe = None;
+                self.signal(libc::SIGKILL);
+            }
+            ProcessState::Starting
+                if self.program.readiness.is_none() && started_for(self.program.start_timeout) =>
+            {
+                self.set_running();
+            }
+            ProcessState::Backoff if self.next_start.is_some_and(|t| t <= now) => {
+                self.restarts += 1;
+                self.spawn();
+            }
+            ProcessState::Stopping if self.stop_deadline.is_some_and(|t| t <= now) => {
+                print_warning_msg!(
+                    "{} 未在 {} 秒内退出, 强制结束",
+                    self.program.name,
+                    self.program.stop_grace_period
+                );
+                self.signal(libc::SIGKILL);
+                self.stop_deadline = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// 每隔 SAMPLE_INTERVAL 从 /proc 读取一次子进程的资源占用,
+    /// 第一次读取时子进程从启动开始累计的 CPU 时间即为两次读取之间的增量
+    fn sample(&mut self) {
+        let Some(child) = &self.child else {
+            return;
+        };
+        let Some(sampled) = self.sampled.filter(|t| t.elapsed() >= SAMPLE_INTERVAL) else {
+            return;
+        };
+        let Some(usage) = resources::usage(child.id()) else {
+            return;
+        };
+        let previous = self.usage.map(|usage| usage.cpu_ticks).unwrap_or(0);
+        let cpu_time =
+            usage.cpu_ticks.saturating_sub(previous) as f64 / resources::clock_ticks() as f64;
+        self.cpu_percent = Some(cpu_time / sampled.elapsed().as_secs_f64() * 100.0);
+        self.usage = Some(usage);
+        self.sampled = Some(Instant::now());
+    }
+
+    /// 子进程退出后根据当前状态决定下一步
+    fn on_exit(&mut self) {
+        let code = self
+            .exit_code
+            .map(|code| code.to_string())
+            .unwrap_or_default();
+        match self.state {
+            ProcessState::Stopping if self.restart_pending => {
+                self.restart_pending = false;
+                self.stop_deadline = None;
+                self.message = self.exit_reason.take().unwrap_or_default();
+                self.schedule_restart();
+            }
+            ProcessState::Stopping => {
+                print_info_msg!("{} 已停止", self.program.name);
+                self.state = ProcessState::Stopped;
+                self.stop_deadline = None;
+                self.message = format!("已停止, 退出码 {}", code);
+            }
+            ProcessState::Starting => {
+                self.message = self
+                    .exit_reason
+                    .take()
+                    .unwrap_or(format!("启动后立即退出, 退出码 {}", code));
+                self.schedule_restart();
+            }
+            ProcessState::Running => {
+                self.state = ProcessState::Exited;
+                self.message = format!("已退出, 退出码 {}", code);
+                let restart = match self.program.restart {
+                    RestartPolicy::Always => true,
+                    RestartPolicy::OnFailure => self.exit_code != Some(0),
+                    RestartPolicy::Never => false,
+                };
+                // 持续运行超过时间窗口后视为已恢复, 重新从 initial 开始退避
+                let window = Duration::from_secs(self.program.crash_loop.window);
+                if self.started.is_some_and(|t| t.elapsed() >= window) {
+                    self.retries = 0;
+                }
+                if restart {
+                    self.schedule_restart();
+                } else {
+                    print_info_msg!("{} 已退出, 退出码 {}", self.program.name, code);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn status(&self) -> ProgramStatus {
+        ProgramStatus {
+            name: self.program.name.clone(),
+            groups: self.program.groups.clone(),
+            state: self.state,
+            pid: self.child.as_ref().map(|child| child.id()),
+            uptime: match (&self.child, self.started) {
+                (Some(_), Some(started)) => started.elapsed().as_secs(),
+                _ => 0,
+            },
+            restarts: self.restarts,
+            exit_code: self.exit_code,
+            message: self.message.clone(),
+            health: match self.child {
+                Some(_) => self.health,
+                None => HealthState::None,
+            },
+            health_message: match self.child {
+                Some(_) => self.health_message.clone(),
+                None => String::new(),
+            },
+            cpu_percent: self.child.as_ref().and(self.cpu_percent),
+            rss: self.child.as_ref().and(self.usage).map(|usage| usage.rss),
+            threads: self
+                .child
+                .as_ref()
+                .and(self.usage)
+                .map(|usage| usage.threads),
+            fds: self.child.as_ref().and(self.usage).map(|usage| usage.fds),
+        }
+    }
+}
+
+/// 进程管理器, 保存所有受管程序的状态
+pub struct Supervisor {
+    /// 安装根目录
+    root: PathBuf,
+
+    /// 所有受管程序, 以程序名称为键
+    processes: BTreeMap<String, Process>,
+
+    /// 读取失败的程序定义, 以模块名称为键, 只在错误变化时打印
+    errors: BTreeMap<String, String>,
+
+    /// supervisord 启动的时刻
+    started: Instant,
+}
+
+impl Supervisor {
+    pub fn new(root: &Path) -> Self {
+        let mut supervisor = Supervisor {
+            root: root.to_path_buf(),
+            processes: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            started: Instant::now(),
+        };
+        supervisor.reload();
+        supervisor
+    }
+
+    /// 重新读取程序定义, 新的定义在程序下一次启动时生效,
+    /// 定义已被删除的程序在停止后移除
+    pub fn reload(&mut self) {
+        let (programs, errors) = program::load_programs(&self.root);
+        let errors: BTreeMap<String, String> = errors.into_iter().collect();
+        for (name, err) in &errors {
+            if self.errors.get(name) != Some(err) {
+                print_error_msg!("{}: {}", name, err);
+            }
+        }
+        self.errors = errors;
+
+        let names: Vec<String> = programs.iter().map(|p| p.name.clone()).collect();
+        for program in programs {
+            match self.processes.get_mut(&program.name) {
+                Some(process) => process.program = program,
+                None => {
+                    let log_path = logfile::log_path(&self.root, &program.name);
+                    self.processes
+                        .insert(program.name.clone(), Process::new(program, log_path));
+                }
+            }
+        }
+        self.processes
+            .retain(|name, process| names.contains(name) || process.child.is_some());
+    }
+
+    /// 所有受管程序的名称
+    pub fn names(&self) -> Vec<String> {
+        self.processes.keys().cloned().collect()
+    }
+
+    /// 所有设置了 autostart 的程序
+    pub fn autostart_names(&self) -> Vec<String> {
+        self.processes
+            .values()
+            .filter(|process| process.program.autostart)
+            .map(|process| process.program.name.clone())
+            .collect()
+    }
+
+    /// 加入 requires 依赖的程序后需要启动的所有程序, 按名称排序, 没有程序定义的依赖被忽略
+    pub fn with_requires(&self, names: &[String]) -> Vec<String> {
+        let mut plan: BTreeSet<String> = BTreeSet::new();
+        let mut queue: Vec<String> = names.to_vec();
+        while let Some(name) = queue.pop() {
+            let Some(process) = self.processes.get(&name) else {
+                continue;
+            };
+            if plan.insert(name) {
+                queue.extend(process.program.requires.iter().cloned());
+            }
+        }
+        plan.into_iter().collect()
+    }
+
+    /// 程序的 requires 依赖
+    pub fn requires(&self, name: &str) -> Vec<String> {
+        self.processes
+            .get(name)
+            .map(|process| process.program.requires.clone())
+            .unwrap_or_default()
+    }
+
+    /// 指定程序之间的依赖关系图, 只包含 names 范围内的依赖
+    pub fn dependency_graph(&self, names: &[String]) -> graph::DependencyGraph {
+        names
+            .iter()
+            .filter_map(|name| self.processes.get(name))
+            .map(|process| {
+                let depends = process
+                    .program
+                    .dependencies()
+                    .filter(|depend| names.contains(depend))
+                    .cloned()
+                    .collect();
+                (process.program.name.clone(), depends)
+            })
+            .collect()
+    }
+
+    /// 启动程序
+    /// # Return
+    /// * Ok(String) - 操作说明
+    /// * Err(String) - 程序不存在、正在停止或因频繁崩溃需要手动重启
+    pub fn start(&mut self, name: &str) -> Result<String, String> {
+        let process = self.process_mut(name)?;
+        match process.state {
+            ProcessState::Starting | ProcessState::Running => Ok("已在运行".to_string()),
+            ProcessState::Stopping => Err(format!("{} 正在停止", name)),
+            ProcessState::Fatal => Err(format!(
+                "{} 因频繁失败已停止, 请排查后使用 supervisord restart 重启",
+                name
+            )),
+            _ => {
+                process.retries = 0;
+                process.crashes.clear();
+                process.message.clear();
+                process.spawn();
+                Ok("已启动".to_string())
+            }
+        }
+    }
+
+    /// 停止程序, 先发送程序定义的停止信号, 超时后发送 SIGKILL
+    /// # Return
+    /// * Ok(String) - 操作说明
+    /// * Err(String) - 程序不存在
+    pub fn stop(&mut self, name: &str) -> Result<String, String> {
+        let process = self.process_mut(name)?;
+        if process.child.is_none() {
+            if process.state != ProcessState::Stopped {
+                process.state = ProcessState::Stopped;
+                process.next_start = None;
+                process.message = "已停止".to_string();
+            }
+            return Ok("未运行".to_string());
+        }
+        process.restart_pending = false;
+        if process.state != ProcessState::Stopping {
+            process.signal(process.program.stop_signal_number());
+            process.state = ProcessState::Stopping;
+            process.stop_deadline =
+                Some(Instant::now() + Duration::from_secs(process.program.stop_grace_period));
+        }
+        Ok("已停止".to_string())
+    }
+
+    /// 程序的状态以及资源限制的配置值与生效值
+    /// # Return
+    /// * Ok((ProgramStatus, Vec<ResourceStatus>)) - 状态与资源限制
+    /// * Err(String) - 程序不存在
+    pub fn detail(&self, name: &str) -> Result<(ProgramStatus, Vec<ResourceStatus>), String> {
+        let process = self
+            .processes
+            .get(name)
+            .ok_or(format!("{} 没有程序定义", name))?;
+        let effective = process
+            .child
+            .as_ref()
+            .map(|child| resources::effective(child.id()))
+            .unwrap_or_default();
+        let resources = process
+            .program
+            .resources
+            .describe()
+            .into_iter()
+            .map(|(name, configured)| ResourceStatus {
+                name: name.to_string(),
+                configured,
+                effective: effective
+                    .iter()
+                    .find(|(item, _)| *item == name)
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        Ok((process.status(), resources))
+    }
+
+    /// 记录程序发送的心跳
+    /// # Return
+    /// * Ok(String) - 操作说明
+    /// * Err(String) - 程序不存在或未运行
+    pub fn heartbeat(&mut self, name: &str) -> Result<String, String> {
+        let process = self.process_mut(name)?;
+        if process.child.is_none() {
+            return Err(format!("{} 未运行", name));
+        }
+        process.heartbeat = Some(Instant::now());
+        Ok("已收到心跳".to_string())
+    }
+
+    /// 检查所有程序的子进程并推进状态
+    pub fn tick(&mut self) {
+        for process in self.processes.values_mut() {
+            process.tick();
+        }
+    }
+
+    /// 是否还有程序的子进程在运行
+    pub fn has_children(&self) -> bool {
+        self.processes
+            .values()
+            .any(|process| process.child.is_some())
+    }
+
+    /// 程序的当前状态
+    pub fn state(&self, name: &str) -> Option<ProcessState> {
+        self.processes.get(name).map(|process| process.state)
+    }
+
+    /// 将程序名称、通配符与分组解析为程序名称, 结果按名称排序并去重
+    /// # Arguments
+    /// * `targets` - 请求作用的程序
+    /// # Return
+    /// * (Vec<String>, Vec<(String, String)>) - 程序名称, 以及没有匹配到程序的 (名称或分组, 原因)
+    pub fn resolve(&self, targets: &Targets) -> (Vec<String>, Vec<(String, String)>) {
+        if targets.all {
+            return (self.names(), Vec::new());
+        }
+
+        let mut names = BTreeSet::new();
+        let mut unmatched = Vec::new();
+        for name in &targets.names {
+            if !name.contains(['*', '?', '[']) {
+                if self.processes.contains_key(name) {
+                    names.insert(name.clone());
+                } else {
+                    unmatched.push((name.clone(), format!("{} 没有程序定义", name)));
+                }
+                continue;
+            }
+            let pattern = match glob::Pattern::new(name) {
+                Ok(pattern) => pattern,
+                Err(err) => {
+                    unmatched.push((name.clone(), format!("通配符 {} 格式错误: {}", name, err)));
+                    continue;
+                }
+            };
+            let matched: Vec<&String> = self
+                .processes
+                .keys()
+                .filter(|program| pattern.matches(program))
+                .collect();
+            if matched.is_empty() {
+                unmatched.push((name.clone(), format!("没有程序匹配 {}", name)));
+            }
+            names.extend(matched.into_iter().cloned());
+        }
+        for group in &targets.groups {
+            let members: Vec<&String> = self
+                .processes
+                .iter()
+                .filter(|(_, process)| process.program.groups.contains(group))
+                .map(|(name, _)| name)
+                .collect();
+            if members.is_empty() {
+                unmatched.push((format!("@{}", group), format!("分组 {} 不存在", group)));
+            }
+            names.extend(members.into_iter().cloned());
+        }
+        (names.into_iter().collect(), unmatched)
+    }
+
+    /// 程序离开 starting 或 stopping 状态最多需要的秒数
+    pub fn timeout(&self, names: &[String], state: ProcessState) -> u64 {
+        names
+            .iter()
+            .filter_map(|name| self.processes.get(name))
+            .map(|process| match state {
+                ProcessState::Stopping => process.program.stop_grace_period,
+                _ => process.program.start_timeout,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 所有程序的状态
+    pub fn status(&self) -> Vec<ProgramStatus> {
+        self.processes.values().map(Process::status).collect()
+    }
+
+    fn process_mut(&mut self, name: &str) -> Result<&mut Process, String> {
+        self.processes
+            .get_mut(name)
+            .ok_or(format!("{} 没有程序定义", name))
+    }
+}
+
+/// 在前台运行 supervisord, 启动 autostart 程序并处理控制套接字上的请求,
+/// 收到 SIGTERM/SIGINT 后停止所有程序并退出
+/// # Arguments
+/// * `root` - 安装根目录
+/// # Return
+/// * Ok(()) - 正常退出
+/// * Err(String) - supervisord 已在运行或控制套接字创建失败
+pub fn run(root: &Path) -> Result<(), String> {
+    let path = protocol::socket_path(root);
+    if UnixStream::connect(&path).is_ok() {
+        return Err(format!("supervisord 已在运行: {}", path.display()));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+        // 以其他用户运行的程序需要进入该目录连接心跳套接字
+        fs::set_permissions(parent, fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("设置 {} 的权限失败: {}", parent.display(), e))?;
+    }
+    let listener = bind(&path, protocol::SOCKET_MODE)?;
+    let heartbeat_path = protocol::heartbeat_socket_path(root);
+    let heartbeat_listener = bind(&heartbeat_path, protocol::HEARTBEAT_SOCKET_MODE)?;
+
+    // SAFETY: 信号处理函数只写入一个原子变量
+    unsafe {
+        libc::signal(
+            libc::SIGTERM,
+            on_shutdown_signal as *const () as libc::sighandler_t,
+        );
+        libc::signal(
+            libc::SIGINT,
+            on_shutdown_signal as *const () as libc::sighandler_t,
+        );
+    }
+
+    resources::init_cgroup();
+    print_info_msg!("supervisord 已启动, 控制套接字 {}", path.display());
+    let supervisor = Arc::new(Mutex::new(Supervisor::new(root)));
+
+    // 按依赖顺序启动 autostart 程序, 等待就绪的过程中主循环继续检查进程状态
+    let shared = supervisor.clone();
+    thread::spawn(move || {
+        let names = lock(&shared).autostart_names();
+        start_ordered(&shared, &names);
+    });
+
+    let shared = supervisor.clone();
+    let socket = path.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let supervisor = shared.clone();
+            let socket = socket.clone();
+            thread::spawn(move || serve(stream, &socket, &supervisor));
+        }
+    });
+
+    let shared = supervisor.clone();
+    thread::spawn(move || {
+        for stream in heartbeat_listener.incoming().flatten() {
+            let supervisor = shared.clone();
+            thread::spawn(move || serve_heartbeat(stream, &supervisor));
+        }
+    });
+
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        lock(&supervisor).tick();
+        thread::sleep(TICK_INTERVAL);
+    }
+
+    // 按依赖的逆序停止所有程序
+    print_info_msg!("正在停止所有程序...");
+    let shared = supervisor.clone();
+    let stopping = thread::spawn(move || {
+        let names = lock(&shared).names();
+        stop_ordered(&shared, &names);
+    });
+    loop {
+        let mut supervisor = lock(&supervisor);
+        supervisor.tick();
+        if stopping.is_finished() && !supervisor.has_children() {
+            break;
+        }
+        drop(supervisor);
+        thread::sleep(TICK_INTERVAL);
+    }
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&heartbeat_path);
+    print_info_msg!("supervisord 已退出");
+    Ok(())
+}
+
+/// 创建套接字并设置权限, 已存在的同名文件会被删除
+fn bind(path: &Path, mode: u32) -> Result<UnixListener, String> {
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)
+        .map_err(|e| format!("创建套接字 {} 失败: {}", path.display(), e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("设置 {} 的权限失败: {}", path.display(), e))?;
+    Ok(listener)
+}
+
+extern "C" fn on_shutdown_signal(_: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+fn lock(supervisor: &Mutex<Supervisor>) -> MutexGuard<'_, Supervisor> {
+    supervisor.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 处理一个客户端连接上的请求
+fn serve(stream: UnixStream, path: &Path, supervisor: &Mutex<Supervisor>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let _ = stream.set_read_timeout(Some(protocol::SERVER_READ_TIMEOUT));
+    let request = protocol::check_peer(&stream, path)
+        .and_then(|_| protocol::read_request(&mut BufReader::new(stream)));
+    let response = match request {
+        Ok(Some(request)) => {
+            // 处理期间定期发送进度消息, 应答前结束发送, 避免与应答交错
+            let (done, finished) = mpsc::channel::<()>();
+            let mut progress = writer.try_clone();
+            thread::scope(|scope| {
+                scope.spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) =
+                        finished.recv_timeout(protocol::PROGRESS_INTERVAL)
+                    {
+                        let sent = progress
+                            .as_mut()
+                            .map_err(|e| e.to_string())
+                            .and_then(protocol::write_progress);
+                        if sent.is_err() {
+                            break;
+                        }
+                    }
+                });
+                let response = handle(request, supervisor);
+                drop(done);
+                response
+            })
+        }
+        Ok(None) => return,
+        Err(err) => Response {
+            error: Some(err),
+            ..Default::default()
+        },
+    };
+    let _ = protocol::write_response(&mut writer, &response);
+}
+
+/// 处理心跳套接字上的连接, 只接受 heartbeat 请求, 且客户端必须是 root、
+/// supervisord 的运行用户或该程序的运行用户
+fn serve_heartbeat(stream: UnixStream, supervisor: &Mutex<Supervisor>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let _ = stream.set_read_timeout(Some(protocol::SERVER_READ_TIMEOUT));
+    let cred = protocol::peer_credentials(&stream);
+    let request = protocol::read_request(&mut BufReader::new(stream));
+    let response = match (cred, request) {
+        (_, Ok(None)) => return,
+        (Ok(cred), Ok(Some(Request::Heartbeat { name }))) => {
+            match check_heartbeat_peer(supervisor, &name, cred.uid) {
+                Ok(()) => Response {
+                    results: apply(supervisor, &[name], Supervisor::heartbeat),
+                    ..Default::default()
+                },
+                Err(err) => Response {
+                    error: Some(err),
+                    ..Default::default()
+                },
+            }
+        }
+        (Ok(_), Ok(Some(_))) => Response {
+            error: Some("心跳套接字只接受 heartbeat 请求".to_string()),
+            ..Default::default()
+        },
+        (Err(err), _) | (_, Err(err)) => Response {
+            error: Some(err),
+            ..Default::default()
+        },
+    };
+    let _ = protocol::write_response(&mut writer, &response);
+}
+
+/// 检查客户端是否可以为程序发送心跳, 程序未定义 user 时以 supervisord 的运行用户运行
+fn check_heartbeat_peer(
+    supervisor: &Mutex<Supervisor>,
+    name: &str,
+    uid: u32,
+) -> Result<(), String> {
+    // SAFETY: geteuid 没有参数且总是成功
+    let euid = unsafe { libc::geteuid() };
+    if uid == 0 || uid == euid {
+        return Ok(());
+    }
+    let user = lock(supervisor)
+        .processes
+        .get(name)
+        .map(|process| process.program.user.clone())
+        .ok_or(format!("{} 没有程序定义", name))?;
+    if !user.is_empty() && common::user_ids(&user).is_some_and(|(user_uid, _)| user_uid == uid) {
+        Ok(())
+    } else {
+        Err(format!(
+            "uid {} 不是 {} 的运行用户, 不能发送心跳",
+            uid, name
+        ))
+    }
+}
+
+/// 执行请求, 启动与停止操作会等待程序离开 starting/stopping 状态后再应答
+fn handle(request: Request, supervisor: &Mutex<Supervisor>) -> Response {
+    match request {
+        Request::Ping {} => {
+            let supervisor = lock(supervisor);
+            Response {
+                daemon: Some(DaemonInfo {
+                    pid: std::process::id(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    protocol: protocol::PROTOCOL_VERSION,
+                    uptime: supervisor.started.elapsed().as_secs(),
+                    programs: supervisor.processes.len(),
+                }),
+                ..Default::default()
+            }
+        }
+        Request::Start(targets) => {
+            let (names, mut results) = resolve(supervisor, &targets);
+            results.extend(start_ordered(supervisor, &names));
+            Response {
+                results,
+                ..Default::default()
+            }
+        }
+        Request::Stop(targets) => {
+            let (names, mut results) = resolve(supervisor, &targets);
+            results.extend(stop_ordered(supervisor, &names));
+            Response {
+                results,
+                ..Default::default()
+            }
+        }
+        Request::Restart(targets) => {
+            let (names, mut results) = resolve(supervisor, &targets);
+            let stopped = stop_ordered(supervisor, &names);
+            let started: Vec<String> = stopped
+                .iter()
+                .filter(|result| result.ok)
+                .map(|result| result.name.clone())
+                .collect();
+            results.extend(stopped.into_iter().filter(|result| !result.ok));
+            results.extend(start_ordered(supervisor, &started));
+            Response {
+                results,
+                ..Default::default()
+            }
+        }
+        Request::Heartbeat { name } => {
+            let results = apply(supervisor, &[name], Supervisor::heartbeat);
+            Response {
+                results,
+                ..Default::default()
+            }
+        }
+        Request::Status { name } => {
+            let mut supervisor = lock(supervisor);
+            supervisor.reload();
+            match supervisor.detail(&name) {
+                Ok((status, resources)) => Response {
+                    programs: vec![status],
+                    resources,
+                    ..Default::default()
+                },
+                Err(err) => Response {
+                    error: Some(err),
+                    ..Default::default()
+                },
+            }
+        }
+        Request::List {} => {
+            let mut supervisor = lock(supervisor);
+            supervisor.reload();
+            Response {
+                programs: supervisor.status(),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+/// 确定请求作用的程序, 同时重新读取程序定义以便发现新安装的模块
+/// # Return
+/// * (Vec<String>, Vec<ActionResult>) - 程序名称, 以及没有匹配到程序的名称或分组对应的失败结果
+fn resolve(supervisor: &Mutex<Supervisor>, targets: &Targets) -> (Vec<String>, Vec<ActionResult>) {
+    let mut supervisor = lock(supervisor);
+    supervisor.reload();
+    let (names, unmatched) = supervisor.resolve(targets);
+    let results = unmatched
+        .into_iter()
+        .map(|(name, message)| ActionResult {
+            name,
+            ok: false,
+            state: None,
+            message,
+        })
+        .collect();
+    (names, results)
+}
+
+/// 按依赖顺序分批启动程序, requires 依赖的程序会一并启动, 每批程序离开 starting 状态后
+/// 再启动下一批, requires 依赖的程序未进入 running 状态(配置了就绪检查时即未通过就绪检查)时不启动
+/// # Arguments
+/// * `supervisor` - 进程管理器
+/// * `names` - 需要启动的程序
+/// # Return
+/// * Vec<ActionResult> - 每个程序的启动结果, 包括一并启动的依赖程序
+fn start_ordered(supervisor: &Mutex<Supervisor>, names: &[String]) -> Vec<ActionResult> {
+    let (plan, graph) = {
+        let supervisor = lock(supervisor);
+        let plan = supervisor.with_requires(names);
+        let graph = supervisor.dependency_graph(&plan);
+        (plan, graph)
+    };
+    if let Err(err) = graph::topological_sort(&graph) {
+        let supervisor = lock(supervisor);
+        return plan
+            .into_iter()
+            .map(|name| ActionResult {
+                state: supervisor.state(&name),
+                name,
+                ok: false,
+                message: err.clone(),
+            })
+            .collect();
+    }
+
+    let mut pending: BTreeSet<String> = plan.into_iter().collect();
+    let mut results = Vec::new();
+    while !pending.is_empty() && !SHUTDOWN.load(Ordering::SeqCst) {
+        // 依赖都已处理完的程序组成一批
+        let wave: Vec<String> = pending
+            .iter()
+            .filter(|name| graph[*name].iter().all(|depend| !pending.contains(depend)))
+            .cloned()
+            .collect();
+        for name in &wave {
+            pending.remove(name);
+        }
+
+        let mut startable = Vec::new();
+        {
+            let supervisor = lock(supervisor);
+            for name in wave {
+                let unready = supervisor
+                    .requires(&name)
+                    .into_iter()
+                    .find(|depend| supervisor.state(depend) != Some(ProcessState::Running));
+                match unready {
+                    Some(depend) => results.push(ActionResult {
+                        state: supervisor.state(&name),
+                        name,
+                        ok: false,
+                        message: format!("依赖的程序 {} 未就绪", depend),
+                    }),
+                    None => startable.push(name),
+                }
+            }
+        }
+        results.extend(apply(supervisor, &startable, Supervisor::start));
+        wait_while(supervisor, &startable, ProcessState::Starting);
+    }
+    finish(supervisor, &mut results, ProcessState::Running);
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    results
+}
+
+/// 按依赖的逆序分批停止程序, 依赖某个程序的其他程序都停止后再停止该程序,
+/// 存在循环依赖时剩余的程序一起停止
+/// # Arguments
+/// * `supervisor` - 进程管理器
+/// * `names` - 需要停止的程序
+/// # Return
+/// * Vec<ActionResult> - 每个程序的停止结果
+fn stop_ordered(supervisor: &Mutex<Supervisor>, names: &[String]) -> Vec<ActionResult> {
+    let graph = lock(supervisor).dependency_graph(names);
+    let mut pending: BTreeSet<String> = names.iter().cloned().collect();
+    let mut results = Vec::new();
+    while !pending.is_empty() {
+        let mut wave: Vec<String> = pending
+            .iter()
+            .filter(|name| {
+                !pending.iter().any(|other| {
+                    graph
+                        .get(other)
+                        .is_some_and(|depends| depends.contains(name))
+                })
+            })
+            .cloned()
+            .collect();
+        if wave.is_empty() {
+            wave = pending.iter().cloned().collect();
+        }
+        for name in &wave {
+            pending.remove(name);
+        }
+        results.extend(apply(supervisor, &wave, Supervisor::stop));
+        wait_while(supervisor, &wave, ProcessState::Stopping);
+    }
+    finish(supervisor, &mut results, ProcessState::Stopped);
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    results
+}
+
+/// 对每个程序执行操作
+fn apply(
+    supervisor: &Mutex<Supervisor>,
+    names: &[String],
+    action: fn(&mut Supervisor, &str) -> Result<String, String>,
+) -> Vec<ActionResult> {
+    let mut supervisor = lock(supervisor);
+    names
+        .iter()
+        .map(|name| {
+            let (ok, message) = match action(&mut supervisor, name) {
+                Ok(message) => (true, message),
+                Err(message) => (false, message),
+            };
+            ActionResult {
+                name: name.clone(),
+                ok,
+                state: supervisor.state(name),
+                message,
+            }
+        })
+        .collect()
+}
+
+/// 等待程序离开 starting 或 stopping 状态, 最多等待程序定义的启动或停止时长再多 2 秒
+fn wait_while(supervisor: &Mutex<Supervisor>, names: &[String], state: ProcessState) {
+    let timeout = lock(supervisor).timeout(names, state) + 2;
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    while Instant::now() < deadline {
+        let pending = {
+            let supervisor = lock(supervisor);
+            names
+                .iter()
+                .any(|name| supervisor.state(name) == Some(state))
+        };
+        if !pending {
+            break;
+        }
+        thread::sleep(TICK_INTERVAL);
+    }
+}
+
+/// 根据程序的最终状态更新操作结果, 未达到期望状态的程序视为失败
+fn finish(supervisor: &Mutex<Supervisor>, results: &mut [ActionResult], expected: ProcessState) {
+    let supervisor = lock(supervisor);
+    let status = supervisor.status();
+    for result in results.iter_mut() {
+        let Some(status) = status.iter().find(|status| status.name == result.name) else {
+            continue;
+        };
+        result.state = Some(status.state);
+        if !result.ok {
+            continue;
+        }
+        result.ok = status.state == expected;
+        if !result.ok {
+            result.message = if status.message.is_empty() {
+                format!("未能进入 {} 状态", expected)
+            } else {
+                status.message.clone()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(name: &str, groups: &[&str]) -> Program {
+        let mut program: Program =
+            toml::from_str(&format!("command = \"/bin/true\"\ngroups = {:?}", groups)).unwrap();
+        program.name = name.to_string();
+        program
+    }
+
+    fn process(backoff: program::Backoff) -> Process {
+        let mut program = program("planning", &[]);
+        program.backoff = backoff;
+        Process::new(program, PathBuf::from("/nonexistent/planning.log"))
+    }
+
+    fn supervisor(programs: &[(&str, &[&str])]) -> Supervisor {
+        let processes = programs
+            .iter()
+            .map(|(name, groups)| {
+                let process = Process::new(program(name, groups), PathBuf::new());
+                (name.to_string(), process)
+            })
+            .collect();
+        Supervisor {
+            root: PathBuf::new(),
+            processes,
+            errors: BTreeMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    fn targets(names: &[&str], groups: &[&str]) -> Targets {
+        Targets {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            all: false,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_max() {
+        let mut process = process(program::Backoff {
+            initial: 1.0,
+            max: 10.0,
+            multiplier: 2.0,
+            jitter: 0.0,
+        });
+        let delays: Vec<u64> = (0..6)
+            .map(|retries| {
+                process.retries = retries;
+                process.backoff_delay().as_secs()
+            })
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn backoff_survives_many_retries() {
+        let mut process = process(program::Backoff {
+            jitter: 0.0,
+            ..Default::default()
+        });
+        process.retries = u32::MAX;
+        assert_eq!(process.backoff_delay(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn backoff_is_clamped_for_unchecked_values() {
+        let mut process = process(program::Backoff {
+            initial: 1e20,
+            max: f64::INFINITY,
+            multiplier: 2.0,
+            jitter: 0.0,
+        });
+        process.retries = 3;
+        assert_eq!(
+            process.backoff_delay(),
+            Duration::from_secs_f64(program::MAX_BACKOFF_SECONDS)
+        );
+        process.program.backoff.jitter = f64::NAN;
+        assert_eq!(process.backoff_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_bounds() {
+        let mut process = process(program::Backoff {
+            initial: 4.0,
+            max: 60.0,
+            multiplier: 1.0,
+            jitter: 0.5,
+        });
+        for _ in 0..100 {
+            process.retries = 0;
+            let delay = process.backoff_delay().as_secs_f64();
+            assert!((2.0..=6.0).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn repeated_failures_within_window_become_fatal() {
+        let mut process = process(program::Backoff::default());
+        process.program.crash_loop.max_failures = 3;
+        process.program.crash_loop.window = 60;
+        process.schedule_restart();
+        process.schedule_restart();
+        assert_eq!(process.state, ProcessState::Backoff);
+        assert_eq!(process.retries, 2);
+        assert!(process.next_start.is_some());
+        process.schedule_restart();
+        assert_eq!(process.state, ProcessState::Fatal);
+        assert!(process.next_start.is_none());
+    }
+
+    #[test]
+    fn failures_outside_window_are_forgotten() {
+        let mut process = process(program::Backoff::default());
+        process.program.crash_loop.max_failures = 2;
+        process.program.crash_loop.window = 60;
+        let old = Instant::now() - Duration::from_secs(120);
+        process.crashes.extend([old, old, old]);
+        process.schedule_restart();
+        assert_eq!(process.state, ProcessState::Backoff);
+        assert_eq!(process.crashes.len(), 1);
+    }
+
+    #[test]
+    fn resolve_matches_names_globs_and_groups() {
+        let supervisor = supervisor(&[
+            ("perception_lidar", &["sensors"]),
+            ("perception_camera", &["sensors"]),
+            ("planning", &["core"]),
+            ("control", &["core"]),
+        ]);
+
+        let (names, unmatched) = supervisor.resolve(&targets(&["perception_*"], &[]));
+        assert_eq!(names, vec!["perception_camera", "perception_lidar"]);
+        assert!(unmatched.is_empty());
+
+        let (names, unmatched) = supervisor.resolve(&targets(&["planning"], &["sensors"]));
+        assert_eq!(
+            names,
+            vec!["perception_camera", "perception_lidar", "planning"]
+        );
+        assert!(unmatched.is_empty());
+
+        let (names, _) = supervisor.resolve(&targets(&["plan?ing", "control"], &["core"]));
+        assert_eq!(names, vec!["control", "planning"]);
+
+        let all = Targets {
+            all: true,
+            ..Default::default()
+        };
+        assert_eq!(supervisor.resolve(&all).0.len(), 4);
+    }
+
+    #[test]
+    fn resolve_reports_unmatched_targets() {
+        let supervisor = supervisor(&[("planning", &["core"])]);
+        let (names, unmatched) =
+            supervisor.resolve(&targets(&["mapping", "loc*", "[bad"], &["sensors"]));
+        assert!(names.is_empty());
+        let unmatched: Vec<&str> = unmatched.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(unmatched, vec!["mapping", "loc*", "[bad", "@sensors"]);
+    }
+}
@@ -0,0 +1,165 @@
+/*
+ * Author: daojin.xu101@gmail.com
+ * Date: 24-8-16
+ * File: version
+ */
+
+use semver::{BuildMetadata, Prerelease, Version};
+
+/// 严格解析模块版本号
+/// 除 SemVer 规范外, 预发布标识中的每一段必须是纯数字或纯字母,
+/// 避免 1.2.0-final2 这类版本因为按字符串比较而导致排序错误, 应写为 1.2.0-final.2
+/// # Arguments
+/// * `version` - 版本号字符串
+/// # Return
+/// * Ok(Version) - 解析后的版本号
+/// * Err(String) - 版本号不合法的原因
+pub fn parse_version(version: &str) -> Result<Version, String> {
+    let parsed = Version::parse(version).map_err(|e| {
+        format!(
+            "版本号 {} 不符合 SemVer 规范(ex: 1.2.0, 1.2.0-rc.1): {}",
+            version, e
+        )
+    })?;
+
+    for identifier in parsed.pre.as_str().split('.').filter(|s| !s.is_empty()) {
+        let numeric = identifier.chars().all(|c| c.is_ascii_digit());
+        let alphabetic = identifier
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || c == '-');
+        if !numeric && !alphabetic {
+            return Err(format!(
+                "版本号 {} 的预发布标识 {} 同时包含字母和数字, 请使用 '.' 分隔, ex: {}",
+                version,
+                identifier,
+                split_identifier(identifier)
+            ));
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// 按指定的部分递增版本号
+/// # Arguments
+/// * `version` - 当前版本号
+/// * `part` - 递增的部分, major/minor/patch/pre
+/// * `pre_id` - 预发布标识, 仅在当前版本不是预发布版本且 part 为 pre 时使用
+/// # Return
+/// * Ok(Version) - 递增后的版本号
+/// * Err(String) - 版本号不合法或 part 不支持
+pub fn bump_version(version: &Version, part: &str, pre_id: &str) -> Result<Version, String> {
+    let mut bumped = version.clone();
+    bumped.build = BuildMetadata::EMPTY;
+    let is_pre = !version.pre.is_empty();
+
+    // 预发布版本递增时优先发布为对应的正式版本, ex: 2.0.0-rc.1 --major--> 2.0.0
+    match part {
+        "major" => {
+            if !(is_pre && version.minor == 0 && version.patch == 0) {
+                bumped.major += 1;
+            }
+            bumped.minor = 0;
+            bumped.patch = 0;
+            bumped.pre = Prerelease::EMPTY;
+        }
+        "minor" => {
+            if !(is_pre && version.patch == 0) {
+                bumped.minor += 1;
+            }
+            bumped.patch = 0;
+            bumped.pre = Prerelease::EMPTY;
+        }
+        "patch" => {
+            if !is_pre {
+                bumped.patch += 1;
+            }
+            bumped.pre = Prerelease::EMPTY;
+        }
+        "pre" => {
+            let pre = if is_pre {
+                // 最后一段为数字时递增, 否则追加 .1, ex: rc.1 -> rc.2, rc -> rc.1
+                let mut identifiers: Vec<String> =
+                    version.pre.as_str().split('.').map(String::from).collect();
+                match identifiers.last().and_then(|s| s.parse::<u64>().ok()) {
+                    Some(number) => *identifiers.last_mut().unwrap() = (number + 1).to_string(),
+                    None => identifiers.push("1".to_string()),
+                }
+                identifiers.join(".")
+            } else {
+                bumped.patch += 1;
+                format!("{}.1", pre_id)
+            };
+            bumped.pre = Prerelease::new(&pre).map_err(|e| e.to_string())?;
+        }
+        _ => return Err(format!("不支持的版本号递增方式: {}", part)),
+    }
+
+    parse_version(&bumped.to_string())
+}
+
+/// 将字母和数字混合的标识拆分为以 '.' 分隔的形式, ex: final2 -> final.2
+fn split_identifier(identifier: &str) -> String {
+    let mut result = String::new();
+    let mut last_is_digit: Option<bool> = None;
+    for c in identifier.chars() {
+        let is_digit = c.is_ascii_digit();
+        if last_is_digit.is_some_and(|last| last != is_digit) {
+            result.push('.');
+        }
+        result.push(c);
+        last_is_digit = Some(is_digit);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bump(version: &str, part: &str) -> String {
+        bump_version(&parse_version(version).unwrap(), part, "rc")
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn release_versions_bump_the_given_part() {
+        assert_eq!(bump("1.2.3", "major"), "2.0.0");
+        assert_eq!(bump("1.2.3", "minor"), "1.3.0");
+        assert_eq!(bump("1.2.3", "patch"), "1.2.4");
+        assert_eq!(bump("1.2.3", "pre"), "1.2.4-rc.1");
+    }
+
+    #[test]
+    fn prerelease_versions_are_released_first() {
+        assert_eq!(bump("2.0.0-rc.1", "major"), "2.0.0");
+        assert_eq!(bump("1.2.3-rc.1", "major"), "2.0.0");
+        assert_eq!(bump("1.3.0-rc.1", "minor"), "1.3.0");
+        assert_eq!(bump("1.3.1-rc.1", "minor"), "1.4.0");
+        assert_eq!(bump("1.2.4-rc.1", "patch"), "1.2.4");
+    }
+
+    #[test]
+    fn prerelease_counter_is_incremented_or_appended() {
+        assert_eq!(bump("1.2.4-rc.1", "pre"), "1.2.4-rc.2");
+        assert_eq!(bump("1.2.4-rc.9", "pre"), "1.2.4-rc.10");
+        assert_eq!(bump("1.2.4-beta", "pre"), "1.2.4-beta.1");
+    }
+
+    #[test]
+    fn build_metadata_is_dropped() {
+        assert_eq!(bump("1.2.3+build.5", "patch"), "1.2.4");
+    }
+
+    #[test]
+    fn unknown_part_is_rejected() {
+        assert!(bump_version(&parse_version("1.2.3").unwrap(), "micro", "rc").is_err());
+    }
+
+    #[test]
+    fn mixed_prerelease_identifiers_are_rejected() {
+        assert!(parse_version("1.2.0-final2").is_err());
+        assert!(parse_version("1.2.0-final.2").is_ok());
+    }
+}
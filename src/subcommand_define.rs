@@ -4,7 +4,7 @@
  * File: subcommand_define
  */
 
-use clap::Parser;
+use clap::{Args, Parser};
 
 /// 枚举定义 module 命令下的所有子命令
 #[derive(Parser)]
@@ -24,8 +24,67 @@ pub enum ModuleCmds {
 
     #[command(about = "构建当前模块")]
     Build {
-        #[arg(long, help = "构建时所需的线程数量")]
+        #[arg(long, default_value_t = 1, help = "构建时所需的线程数量")]
         jobs: u32,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "按依赖顺序构建当前目录下的所有模块, 相互独立的模块并行构建"
+        )]
+        workspace: bool,
+    },
+
+    #[command(about = "校验已安装模块的文件是否被篡改或损坏")]
+    Verify {
+        #[arg(long, help = "模块名称, 不指定时校验所有已安装的模块")]
+        name: Option<String>,
+    },
+
+    #[command(about = "卸载已安装的模块, 卸载前会备份模块")]
+    Remove {
+        #[arg(long, help = "模块名称")]
+        name: String,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "忽略其他模块对该模块的依赖, 强制卸载"
+        )]
+        force: bool,
+    },
+
+    #[command(about = "从备份中恢复已卸载或被替换的模块")]
+    Rollback {
+        #[arg(long, help = "模块名称")]
+        name: String,
+
+        #[arg(long, help = "需要恢复的版本号, 不指定时恢复最近一次的备份")]
+        version: Option<String>,
+
+        #[arg(long, default_value_t = false, help = "模块已安装时替换当前安装的版本")]
+        force: bool,
+    },
+
+    #[command(about = "切换模块当前生效的版本")]
+    Activate {
+        #[arg(long, help = "模块名称")]
+        name: String,
+
+        #[arg(long, help = "需要切换到的版本号, 必须已经安装")]
+        version: String,
+    },
+
+    #[command(about = "将当前模块打包为可安装的软件包")]
+    Package {
+        #[arg(long, value_parser = ["deb", "run"], default_value_t = String::from("deb"), help = "软件包格式, deb: Debian 软件包, run: 自解压安装包")]
+        format: String,
+
+        #[arg(long, default_value_t = String::from("install"), help = "模块的安装目录树")]
+        install_dir: String,
+
+        #[arg(short = 'o', long, default_value_t = String::from("."), help = "软件包的输出目录")]
+        output_dir: String,
     },
 
     #[command(about = "设置或查看当前模块的配置信息")]
@@ -34,16 +93,35 @@ pub enum ModuleCmds {
         sub_cmd: ConfigSubCmd,
     },
 
+    #[command(about = "管理当前模块的版本号")]
+    Version {
+        #[command(subcommand)]
+        sub_cmd: VersionSubCmd,
+    },
+
     #[command(about = "列出软件包的简要信息")]
     List {
         #[arg(short, long, help = "指定模块的名字")]
         name: Option<String>,
+
+        #[arg(long, help = "只列出带有指定标签的模块, ex: perception")]
+        tag: Option<String>,
     },
 
     #[command(about = "列出指定模块所包含的文件")]
     ListFiles {
         #[arg(long, help = "模块名称")]
         name: String,
+
+        #[arg(long, default_value_t = false, help = "以目录树的形式显示文件")]
+        tree: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "与安装时记录的文件清单比较, 列出被修改、缺失以及多余的文件"
+        )]
+        check: bool,
     },
 }
 
@@ -51,25 +129,97 @@ pub enum ModuleCmds {
 #[derive(Parser)]
 pub enum ConfigSubCmd {
     #[command(about = "设置当前模块的配置信息")]
-    Set {
-        #[arg(long, help = "模块名称")]
-        name: Option<String>,
+    Set(Box<ConfigSetArgs>),
 
-        #[arg(long, help = "模块版本号")]
-        version: Option<String>,
+    #[command(about = "获取当前模块的配置信息")]
+    Get {},
 
-        #[arg(long, value_parser = ["amd64", "arm64", "all"], help = "模块所属平台")]
-        architecture: Option<String>,
+    #[command(about = "校验模块配置文件的格式, 并给出出错的行号和列号")]
+    Validate {
+        #[arg(long, default_value_t = String::from("version.toml"), help = "需要校验的配置文件")]
+        file: String,
+    },
+}
+
+/// 枚举定义 module version 命令下的所有子命令
+#[derive(Parser)]
+pub enum VersionSubCmd {
+    #[command(about = "递增当前模块的版本号并写入 version.toml")]
+    Bump {
+        #[arg(value_parser = ["major", "minor", "patch", "pre"], help = "递增的部分")]
+        part: String,
 
-        #[arg(long, help = "模块作者信息")]
-        author: Option<String>,
+        #[arg(long, default_value_t = String::from("rc"), help = "预发布标识, 仅在递增 pre 时使用")]
+        pre_id: String,
 
-        #[arg(long, help = "模块描述信息")]
-        description: Option<String>,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "递增后创建 git 标签, 格式: <name>-v<version>"
+        )]
+        tag: bool,
     },
+}
 
-    #[command(about = "获取当前模块的配置信息")]
-    Get {},
+/// module config set 命令的参数
+#[derive(Args)]
+pub struct ConfigSetArgs {
+    #[arg(long, help = "模块名称")]
+    pub name: Option<String>,
+
+    #[arg(long, help = "模块版本号")]
+    pub version: Option<String>,
+
+    #[arg(long, value_parser = ["amd64", "arm64", "all"], help = "模块所属平台")]
+    pub architecture: Option<String>,
+
+    #[arg(long, help = "模块作者信息")]
+    pub author: Option<String>,
+
+    #[arg(long, help = "模块描述信息")]
+    pub description: Option<String>,
+
+    #[arg(
+        long,
+        help = "依赖的模块, 可多次指定, ex: --depends \"perception (>= 1.2.0)\", 传入空字符串时清空"
+    )]
+    pub depends: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        help = "依赖的系统软件包, 格式同 Debian 的 Depends 字段, ex: \"libfoo (>= 2:1.0-1ubuntu2)\", 可多次指定, 传入空字符串时清空"
+    )]
+    pub system_depends: Option<Vec<String>>,
+
+    #[arg(long, help = "冲突的模块, 可多次指定, 传入空字符串时清空")]
+    pub conflicts: Option<Vec<String>>,
+
+    #[arg(long, help = "许可证, ex: Apache-2.0")]
+    pub license: Option<String>,
+
+    #[arg(
+        long,
+        help = "维护者, 可多次指定, ex: --maintainers \"name <email>\", 传入空字符串时清空"
+    )]
+    pub maintainers: Option<Vec<String>>,
+
+    #[arg(long, help = "项目主页, ex: https://example.com/foo")]
+    pub homepage: Option<String>,
+
+    #[arg(long, help = "源码仓库地址, ex: git@example.com:foo.git")]
+    pub repository: Option<String>,
+
+    #[arg(long, help = "标签, 可多次指定, 传入空字符串时清空")]
+    pub tags: Option<Vec<String>>,
+
+    #[arg(long, help = "构建时的 git 提交, 通常由 module package 自动写入软件包")]
+    pub git_commit: Option<String>,
+
+    #[arg(
+        long,
+        help = "构建时间, RFC 3339 格式, 通常由 module package 自动写入软件包"
+    )]
+    pub build_time: Option<String>,
 }
 
 /// 枚举定义 waytous artifact 命令下的所有子命令
@@ -88,7 +238,10 @@ pub enum ArtifactCmds {
     },
 
     #[command(about = "在当前环境下安装指定的 OTA 制品")]
-    Install {},
+    Install {
+        #[arg(short, long, help = "需要安装的 OTA 制品文件")]
+        file: String,
+    },
 
     #[command(about = "修改指定的 OTA 制品")]
     Modify {},
@@ -122,6 +275,12 @@ pub enum WriteSubCmd {
 
         #[arg(short, long, num_args = 1.., value_delimiter = ' ', help = "打包的文件")]
         files: Vec<String>,
+
+        #[arg(
+            long,
+            help = "设备类型, 不指定时使用全局配置中的 device_type, 均未设置时根据当前系统生成"
+        )]
+        device_type: Option<String>,
     },
 
     #[command(about = "创建一个带有启动引导项的 image OTA 制品")]
@@ -131,6 +290,13 @@ pub enum WriteSubCmd {
     RootfsImage {},
 }
 
+/// 枚举定义 waytous config 命令下的所有子命令
+#[derive(Parser)]
+pub enum GlobalConfigCmds {
+    #[command(about = "显示合并后生效的全局配置以及每个配置项的来源")]
+    Show {},
+}
+
 /// 枚举定义 waytous deploy 命令下的所有子命令
 #[derive(Parser)]
 pub enum DeployCmds {
@@ -167,40 +333,130 @@ pub enum InterfaceSubCmd {
     Serial {},
 }
 
-
 #[derive(Parser)]
 pub enum SupervisordCmds {
+    #[command(about = "在前台运行 supervisord, 启动并守护所有定义了 supervise.toml 的模块")]
+    Daemon {},
+
+    #[command(about = "检查 supervisord 是否在运行")]
+    Ping {},
+
     #[command(about = "启动指定的模块")]
     Start {
-        #[arg(short, long, help = "启动所有模块")]
+        #[arg(short, long, conflicts_with_all = ["name", "group"], help = "启动所有模块")]
         all: bool,
 
-        #[arg(short, long, help = "需要启动的模块名称")]
-        name: String,
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            required_unless_present_any = ["all", "group"],
+            help = "需要启动的模块名称, 可以指定多个, 支持通配符, ex: perception_*"
+        )]
+        name: Vec<String>,
+
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            help = "需要启动的模块分组, 分组在 supervise.toml 的 groups 中定义"
+        )]
+        group: Vec<String>,
     },
 
     #[command(about = "重启指定的模块")]
     Restart {
-        #[arg(short, long, help = "重启所有模块")]
+        #[arg(short, long, conflicts_with_all = ["name", "group"], help = "重启所有模块")]
         all: bool,
 
-        #[arg(short, long, help = "需要重启的模块名称")]
-        name: String,
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            required_unless_present_any = ["all", "group"],
+            help = "需要重启的模块名称, 可以指定多个, 支持通配符, ex: perception_*"
+        )]
+        name: Vec<String>,
+
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            help = "需要重启的模块分组, 分组在 supervise.toml 的 groups 中定义"
+        )]
+        group: Vec<String>,
     },
 
     #[command(about = "停止指定的模块")]
     Stop {
-        #[arg(short, long, help = "重启所有模块")]
+        #[arg(short, long, conflicts_with_all = ["name", "group"], help = "停止所有模块")]
         all: bool,
 
-        #[arg(short, long, help = "需要重启的模块名称")]
-        name: String,
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            required_unless_present_any = ["all", "group"],
+            help = "需要停止的模块名称, 可以指定多个, 支持通配符, ex: perception_*"
+        )]
+        name: Vec<String>,
+
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            help = "需要停止的模块分组, 分组在 supervise.toml 的 groups 中定义"
+        )]
+        group: Vec<String>,
     },
 
-    #[command(about = "列出当前系统中的所有模块")]
+    #[command(about = "列出 supervisord 管理的所有模块及其状态与资源占用")]
     List {
+        #[arg(short, long, help = "持续刷新显示, 按 Ctrl-C 退出")]
+        watch: bool,
 
+        #[arg(
+            short,
+            long,
+            default_value_t = 2,
+            value_parser = clap::value_parser!(u64).range(1..),
+            requires = "watch",
+            help = "持续刷新时的间隔秒数"
+        )]
+        interval: u64,
+    },
+
+    #[command(about = "查看模块的状态与资源限制")]
+    Status {
+        #[arg(short, long, help = "模块名称")]
+        name: String,
     },
 
+    #[command(about = "查看模块的输出日志")]
+    Logs {
+        #[arg(short, long, help = "模块名称")]
+        name: String,
+
+        #[arg(short, long, help = "持续输出新写入的日志, 按 Ctrl-C 退出")]
+        follow: bool,
+
+        #[arg(short, long, help = "只显示最近一段时间的日志, ex: 30s, 10m, 2h, 1d")]
+        since: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "只显示匹配该通配符的行, 可以匹配行中的任意部分, ex: error, \"timeout*planning\""
+        )]
+        grep: Option<String>,
+    },
+
+    #[command(about = "向 supervisord 发送心跳, 供 heartbeat 类型的健康检查使用")]
+    Heartbeat {
+        #[arg(short, long, help = "发送心跳的模块名称")]
+        name: String,
+    },
 
+    #[command(about = "校验所有已安装模块的 supervise.toml")]
+    CheckConfig {},
 }